@@ -0,0 +1,59 @@
+use crate::components::*;
+use rltk::Rltk;
+use specs::prelude::*;
+
+/// Rows at the bottom of the console that belong to the ui box.
+pub const UI_HEIGHT: i32 = 7;
+
+/// The part of the map that fits on the console, centered on the player and
+/// kept inside the map wherever the map is big enough for that.
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub min_x: i32,
+    pub min_y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Camera {
+    pub fn new(ecs: &World, ctx: &Rltk) -> Camera {
+        let (width, height) = ctx.get_char_size();
+        Camera::centered(ecs, width as i32, height as i32 - UI_HEIGHT)
+    }
+
+    pub fn centered(ecs: &World, width: i32, height: i32) -> Camera {
+        let map = ecs.fetch::<map::TetraMap>();
+        let player = *ecs.fetch::<Entity>();
+        let (px, py) = ecs
+            .read_storage::<Position>()
+            .get(player)
+            .map_or((0, 0), |p| (p.x, p.y));
+
+        Camera {
+            min_x: (px - width / 2).clamp(0, (map.width() - width).max(0)),
+            min_y: (py - height / 2).clamp(0, (map.height() - height).max(0)),
+            width,
+            height,
+        }
+    }
+
+    /// Where a map tile ends up on the console, if it's in view at all.
+    pub fn to_screen(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        let (sx, sy) = (x - self.min_x, y - self.min_y);
+        if sx >= 0 && sx < self.width && sy >= 0 && sy < self.height {
+            Some((sx, sy))
+        } else {
+            None
+        }
+    }
+
+    /// The map tile under a console cell, none if the cell isn't part of the
+    /// view. On a small map the tile can still lie outside of the map.
+    pub fn to_world(&self, sx: i32, sy: i32) -> Option<(i32, i32)> {
+        if sx >= 0 && sx < self.width && sy >= 0 && sy < self.height {
+            Some((sx + self.min_x, sy + self.min_y))
+        } else {
+            None
+        }
+    }
+}
@@ -1,11 +1,16 @@
 use crate::util::Rect;
+use serde::{Deserialize, Serialize};
 use specs::prelude::*;
 use std::cmp::{max, min};
 
+#[derive(Serialize, Deserialize, Clone)]
 pub struct TetraMap {
     pub buffer: TileBuffer,
     pub rooms: Vec<Room>,
+    // both of these are derived from the tiles and entity positions, so they don't get saved
+    #[serde(skip)]
     pub nav_buffer: Buffer2D<bool>,
+    #[serde(skip)]
     pub entities: Buffer2D<Vec<Entity>>,
 }
 
@@ -21,11 +26,14 @@ impl TetraMap {
         }
     }
 
-    pub fn xy(&self, idx: usize)-> (i32, i32) {
-        (idx as i32 % self.buffer.width,  idx as i32 / self.buffer.width)
+    pub fn xy(&self, idx: usize) -> (i32, i32) {
+        (
+            idx as i32 % self.buffer.width,
+            idx as i32 / self.buffer.width,
+        )
     }
 
-    pub fn xy_idx(&self, x: i32, y: i32)-> usize {
+    pub fn xy_idx(&self, x: i32, y: i32) -> usize {
         self.buffer.xy_idx(x, y)
     }
 
@@ -37,20 +45,53 @@ impl TetraMap {
         self.buffer.height
     }
 
-    pub fn _dimensions(&self) -> (i32, i32) {
-        (self.width(), self.height())
-    }
-
     pub fn gen_nav_buffer(&mut self) {
         update_nav_buffer(&self.buffer.data, &mut self.nav_buffer.data);
     }
 
+    /// Reallocate the nav and entity buffers, eg after the map was deserialized without them.
+    pub fn rebuild_buffers(&mut self) {
+        self.nav_buffer = Buffer2D::new(self.width(), self.height(), false);
+        self.entities = Buffer2D::new(self.width(), self.height(), Vec::new());
+        self.gen_nav_buffer();
+    }
+
     pub fn clear_entities(&mut self) {
         for e in self.entities.data.iter_mut() {
             e.clear();
         }
     }
 
+    /// Every tile a blast going off at (x, y) reaches, walls stop it the same
+    /// way they stop sight.
+    pub fn blast_area(&self, x: i32, y: i32, radius: i32) -> Vec<(i32, i32)> {
+        rltk::field_of_view(rltk::Point::new(x, y), radius, self)
+            .iter()
+            .filter(|p| p.x >= 0 && p.x < self.width() && p.y >= 0 && p.y < self.height())
+            .map(|p| (p.x, p.y))
+            .collect()
+    }
+
+    /// Opens the door at (x, y), false if there is no closed door there.
+    pub fn open_door(&mut self, x: i32, y: i32) -> bool {
+        if *self.buffer.get(x, y) == TileType::DoorClosed {
+            self.buffer.set(x, y, TileType::DoorOpen);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Closes the door at (x, y), unless something is standing in it.
+    pub fn close_door(&mut self, x: i32, y: i32) -> bool {
+        if *self.buffer.get(x, y) == TileType::DoorOpen && self.entities.get(x, y).is_empty() {
+            self.buffer.set(x, y, TileType::DoorClosed);
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn is_blocked(&self, x: i32, y: i32) -> bool {
         self.nav_buffer.data[self.nav_buffer.xy_idx(x, y)]
     }
@@ -78,7 +119,7 @@ impl TetraMap {
     }
 }
 
-fn update_nav_buffer(tiles: &Vec<TileType>, nav: &mut Vec<bool>) {
+fn update_nav_buffer(tiles: &[TileType], nav: &mut [bool]) {
     for (i, tile) in tiles.iter().enumerate() {
         nav[i] = tile == &TileType::Wall;
     }
@@ -88,6 +129,7 @@ pub type TileBuffer = Buffer2D<TileType>;
 
 /// A TileMap is a resource that is shared by the components.
 // pub type TileMap = Vec<TileType>;
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Buffer2D<T> {
     pub height: i32,
     pub width: i32,
@@ -98,7 +140,7 @@ impl<T> Buffer2D<T>
 where
     T: Clone + PartialEq,
 {
-    fn new(width: i32, height: i32, fill: T) -> Buffer2D<T> {
+    pub fn new(width: i32, height: i32, fill: T) -> Buffer2D<T> {
         Buffer2D {
             width,
             height,
@@ -122,26 +164,41 @@ where
 
     pub fn mutate<F>(&mut self, x: i32, y: i32, func: F)
     where
-        F: Fn(&mut T) -> (),
+        F: Fn(&mut T),
     {
         let idx = self.xy_idx(x, y);
         func(&mut self.data[idx]);
     }
+}
 
-    pub fn _contains_at(&self, x: i32, y: i32, tile: &T) -> bool {
-        let idx = self.xy_idx(x, y);
-        &self.data[idx] == tile
+impl<T> Default for Buffer2D<T> {
+    fn default() -> Self {
+        Buffer2D {
+            width: 0,
+            height: 0,
+            data: Vec::new(),
+        }
     }
 }
 
-#[derive(PartialEq, Copy, Clone, Debug)]
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum TileType {
     Wall,
     Floor,
+    DownStairs,
+    UpStairs,
+    /// Closed doors can be walked through, it just takes opening them first.
+    DoorClosed,
+    DoorOpen,
 }
 
-pub type Room = Rect;
+impl TileType {
+    pub fn is_opaque(self) -> bool {
+        matches!(self, TileType::Wall | TileType::DoorClosed)
+    }
+}
 
+pub type Room = Rect;
 
 pub fn new_map_rooms_and_corridors<T>(width: i32, height: i32, rooms: T) -> TetraMap
 where
@@ -149,9 +206,17 @@ where
 {
     // let mut map = vec![TileType::Wall; 80 * 50];
     let mut level = TetraMap::new(TileBuffer::new(width, height, TileType::Wall));
+    let mut rejected = 0;
     rooms.into_iter().for_each(|r| {
-        level.try_add_room(r);
+        if level.try_add_room(r).is_some() {
+            rejected += 1;
+        }
     });
+    log::debug!(
+        "{} rooms placed, {} overlapped and were dropped",
+        level.rooms.len(),
+        rejected
+    );
 
     for (r, p) in level.rooms.iter().skip(1).zip(level.rooms.iter()) {
         let (r_x, r_y) = r.center();
@@ -163,20 +228,22 @@ where
     level
 }
 
-fn apply_horizontal_tunnel(map: &mut TileBuffer, x1: i32, x2: i32, y: i32) {
+pub fn apply_horizontal_tunnel(map: &mut TileBuffer, x1: i32, x2: i32, y: i32) {
     for x in min(x1, x2)..=max(x1, x2) {
-        let idx = map.xy_idx(x, y);
-        if idx > 0 && idx < map.data.len() {
-            map.data[idx as usize] = TileType::Floor;
-        }
+        dig(map, x, y);
     }
 }
 
-fn apply_vertical_tunnel(map: &mut TileBuffer, y1: i32, y2: i32, x: i32) {
+pub fn apply_vertical_tunnel(map: &mut TileBuffer, y1: i32, y2: i32, x: i32) {
     for y in min(y1, y2)..=max(y1, y2) {
-        let idx = map.xy_idx(x, y);
-        if idx > 0 && idx < map.data.len() {
-            map.data[idx as usize] = TileType::Floor;
-        }
+        dig(map, x, y);
+    }
+}
+
+// turns walls into floor, the outermost ring of the map stays solid whatever happens
+fn dig(map: &mut TileBuffer, x: i32, y: i32) {
+    if x > 0 && y > 0 && x < map.width - 1 && y < map.height - 1 && *map.get(x, y) == TileType::Wall
+    {
+        map.set(x, y, TileType::Floor);
     }
 }
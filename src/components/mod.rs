@@ -5,14 +5,27 @@ use specs::{
 };
 use specs_derive::*;
 use std::collections::HashSet;
-use serde::*;
+use crate::util::Dice;
+use serde::{Deserialize, Serialize};
 
 pub mod map;
 pub mod gamelog;
 pub mod spawner;
 pub use gamelog::GameLog;
 
-#[derive(Component, Debug, Clone)]
+/// How many turns the player has taken so far.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Turn(pub u64);
+
+/// How far down the dungeon the player is, the first level is 1.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Depth(pub i32);
+
+/// What dealt the player the blow that killed them, none while they live.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct KilledBy(pub Option<String>);
+
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
 pub struct Position {
     pub x: i32,
     pub y: i32,
@@ -27,80 +40,96 @@ impl From<(i32, i32)> for Position {
 impl Position {
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
 pub struct Viewshed {
     pub visible_tiles: HashSet<usize>,
     pub range: i32,
     pub dirty: bool,
 }
 
-#[derive(Component, Debug, Clone)]
+#[derive(Component, ConvertSaveload, Debug, Clone)]
 pub struct InBackpack{
     pub owner: Entity
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
 pub struct Item {}
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
 pub struct ProvidesHealing {
     pub heal_amount: i32
 }
 
-#[derive(Component, Debug)]
-pub struct Monster;
+// braces, a unit struct goes into the save as null and that reads back as no component at all
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
+pub struct Monster {}
 
-#[derive(Component, Debug, Clone)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
 pub struct Name {
     pub name: String,
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
 pub struct Player {
     pub revealed_tiles: HashSet<usize>,
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
 pub struct BlocksTile {}
 
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
 pub struct CombatStats {
     pub max_hp: i32,
     pub hp: i32,
+    /// added to the armor anybody has to roll against to hit it
     pub defense: i32,
+    /// added to its attack rolls
     pub power: i32,
+    /// what it hits for without a weapon in hand
+    pub damage: Dice,
 }
 
+/// An attack roll has to come up at least this plus the target's defense to hit.
+pub const BASE_ARMOR: i32 = 10;
+
 #[derive(Component, ConvertSaveload,  Debug, Clone)]
 pub struct WantsToMelee {
     pub target: Entity,
 }
 
-#[derive(Component, Debug, Clone)]
+/// Monsters walking into a closed door get it opened for them.
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
+pub struct WantsToOpenDoor {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Component, ConvertSaveload, Debug, Clone)]
 pub struct WantsToPickupItem {
     pub collected_by: Entity,
     pub item: Entity
 }
 
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
 pub struct SufferDamage {
-    pub amount: Vec<i32>
+    /// each hit along with whatever dealt it
+    pub amount: Vec<(i32, String)>
 }
 
 impl SufferDamage {
-    pub fn new_damage(store: &mut WriteStorage<SufferDamage>, victim: Entity, amount: i32) {
+    pub fn new_damage(store: &mut WriteStorage<SufferDamage>, victim: Entity, amount: i32, source: &str) {
         if let Some (suffering) = store.get_mut(victim) {
-            suffering.amount.push(amount);
+            suffering.amount.push((amount, source.to_string()));
         } else {
-            let dmg = SufferDamage {amount: vec! [amount]};
+            let dmg = SufferDamage {amount: vec! [(amount, source.to_string())]};
             store.insert(victim, dmg).expect("Unable to insert damage");
         }
     }
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, ConvertSaveload, Debug, Clone)]
 pub struct WantsToUseItem {
     pub item: Entity,
     pub target: Option<(i32, i32)>
@@ -111,15 +140,226 @@ pub struct WantsToDropItem {
     pub item: Entity,
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
 pub struct Consumable {}
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
 pub struct Ranged {
     pub range: i32
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
 pub struct InflictsDamage {
     pub damage : i32
-}
\ No newline at end of file
+}
+
+/// The item goes off over everything within radius of where it lands, not just the one tile.
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
+pub struct AreaOfEffect {
+    pub radius: i32
+}
+/// Where on the body something gets worn, only one thing fits in each.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub enum EquipmentSlot {
+    Melee,
+    Shield,
+    Head,
+    Body,
+}
+
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
+pub struct Equippable {
+    pub slot: EquipmentSlot,
+}
+
+/// Worn by `owner`. Equipped items are not in the backpack at the same time.
+#[derive(Component, ConvertSaveload, Debug, Clone)]
+pub struct Equipped {
+    pub owner: Entity,
+    pub slot: EquipmentSlot,
+}
+
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
+pub struct MeleePowerBonus {
+    pub power: i32,
+}
+
+/// A weapon that hits for its own dice instead of whatever its wielder would.
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
+pub struct MeleeDamage {
+    pub damage: Dice,
+}
+
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
+pub struct DefenseBonus {
+    pub defense: i32,
+}
+
+#[derive(Component, ConvertSaveload, Debug, Clone)]
+pub struct WantsToRemoveItem {
+    pub item: Entity,
+}
+
+/// Lasting effects, they wear off after a number of rounds.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Copy, Clone, Debug)]
+pub enum StatusKind {
+    /// stumbles around in a random direction instead of doing what it wanted
+    Confusion,
+    /// takes damage every round
+    Poison,
+    /// does nothing until it wears off or something hurts it
+    Sleep,
+    /// acts twice as fast
+    Haste,
+    /// acts half as fast
+    Slow,
+    /// fights on the player's side
+    Charm,
+}
+
+impl StatusKind {
+    pub fn describe(self) -> &'static str {
+        match self {
+            StatusKind::Confusion => "confused",
+            StatusKind::Poison => "poisoned",
+            StatusKind::Sleep => "asleep",
+            StatusKind::Haste => "hasted",
+            StatusKind::Slow => "slowed",
+            StatusKind::Charm => "charmed",
+        }
+    }
+}
+
+/// Damage poison does every round.
+pub const POISON_DAMAGE: i32 = 2;
+
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+pub struct StatusEffect {
+    pub kind: StatusKind,
+    pub turns: i32,
+}
+
+#[derive(Component, Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StatusEffects {
+    pub effects: Vec<StatusEffect>,
+}
+
+impl StatusEffects {
+    pub fn has(&self, kind: StatusKind) -> bool {
+        self.effects.iter().any(|e| e.kind == kind)
+    }
+
+    /// Getting the same effect again only tops up how long it lasts.
+    pub fn apply(store: &mut WriteStorage<StatusEffects>, target: Entity, kind: StatusKind, turns: i32) {
+        if let Some(status) = store.get_mut(target) {
+            match status.effects.iter_mut().find(|e| e.kind == kind) {
+                Some(effect) => effect.turns = i32::max(effect.turns, turns),
+                None => status.effects.push(StatusEffect { kind, turns }),
+            }
+        } else {
+            let status = StatusEffects { effects: vec![StatusEffect { kind, turns }] };
+            store.insert(target, status).expect("Unable to insert status effects");
+        }
+    }
+}
+
+/// Energy a plain action takes, a move or an attack. Everything that acts
+/// saves up energy at its speed every round and goes once it has this much.
+pub const ACTION_COST: i32 = 100;
+
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
+pub struct Energy {
+    /// energy gained every round, ACTION_COST is one action a round
+    pub speed: i32,
+    pub energy: i32,
+}
+
+impl Energy {
+    /// How much energy a round brings in, once haste and slow are accounted for.
+    pub fn gain(&self, status: Option<&StatusEffects>) -> i32 {
+        let has = |kind| status.is_some_and(|s| s.has(kind));
+        let mut gain = self.speed;
+        if has(StatusKind::Haste) {
+            gain *= 2;
+        }
+        if has(StatusKind::Slow) {
+            gain /= 2;
+        }
+        gain
+    }
+
+    pub fn ready(&self) -> bool {
+        self.energy >= ACTION_COST
+    }
+}
+
+/// How a monster goes about things, set from its raws.
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
+pub struct Temperament {
+    /// runs away once its hp drops below this percentage of its max, 0 never does
+    pub flee_below: i32,
+    /// moves around on its own when it has nothing better to do
+    pub wanders: bool,
+    /// how long it keeps looking for its target after losing sight of them
+    pub search_turns: i32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+pub enum AiMode {
+    /// nothing going on, it stays put unless its temperament has it wander
+    Wander,
+    /// somebody it wants to fight is in sight and it's going for them
+    Chase,
+    /// heading for where its target was last seen, gives up after `turns`
+    Search { turns: i32 },
+    /// hurt or scared and getting away
+    Flee,
+}
+
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
+pub struct AiState {
+    pub mode: AiMode,
+    /// where it last saw its target
+    pub last_seen: Option<(i32, i32)>,
+}
+
+/// The faction the player is in. Charmed monsters count as part of it too.
+pub const PLAYER_FACTION: &str = "Player";
+
+/// Who a creature sides with, the raws say how each faction reacts to the others.
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
+pub struct Faction {
+    pub name: String,
+}
+
+impl Faction {
+    /// The faction it acts for right now, being charmed puts it on the player's side.
+    pub fn allegiance(&self, status: Option<&StatusEffects>) -> &str {
+        if status.is_some_and(|s| s.has(StatusKind::Charm)) {
+            PLAYER_FACTION
+        } else {
+            &self.name
+        }
+    }
+}
+
+/// A monster that can hit the player from afar. The ability is its own
+/// entity, set up like a ranged item, and gets used the same way.
+#[derive(Component, ConvertSaveload, Debug, Clone)]
+pub struct RangedAttack {
+    pub ability: Entity,
+    /// actions it has to wait between shots
+    pub cooldown: i32,
+    /// actions left until it can shoot again
+    pub recharge: i32,
+    /// backs off when the player gets closer than this
+    pub keep_distance: i32,
+}
+
+/// Whatever the item is used on gets this status effect.
+#[derive(Component, Serialize, Deserialize, Debug, Clone)]
+pub struct GrantsStatus {
+    pub kind: StatusKind,
+    pub turns: i32,
+}
+
@@ -3,9 +3,13 @@ use crate::components::*;
 
 //TODO clearly  less than ideal
 use crate::draw;
-use crate::util::{Rect, RngResource};
+use crate::saveload::SerializeMe;
+use crate::raws::{ItemRaw, MonsterRaw, RangedAttackRaw, RawMaster, RenderableRaw, DEFAULT_FACTION};
+use crate::util::{Dice, Rng, SpawnRng};
+use log::warn;
 use rltk;
 use rltk::RGB;
+use specs::saveload::{MarkedBuilder, SimpleMarker};
 
 pub fn player(ecs: &mut World, player_x: i32, player_y: i32) -> Entity {
     ecs.create_entity()
@@ -27,174 +31,270 @@ pub fn player(ecs: &mut World, player_x: i32, player_y: i32) -> Entity {
         .with(Name {
             name: "Player".into(),
         })
+        .with(Faction {
+            name: PLAYER_FACTION.into(),
+        })
         .with(CombatStats {
             max_hp: 30,
             hp: 30,
             power: 5,
             defense: 2,
+            damage: Dice::new(1, 4, 1),
+        })
+        // ready to go as soon as the game starts
+        .with(Energy {
+            speed: ACTION_COST,
+            energy: ACTION_COST,
         })
         // .with(BlocksTile{})
+        .marked::<SimpleMarker<SerializeMe>>()
         .build()
 }
 
-pub fn spawn_room(
+/// Scatter monsters and items over the given floor tiles, never two on the same tile.
+pub fn spawn_region(
     ecs: &mut World,
-    room: &Rect,
+    region: &[(i32, i32)],
     SpawnerSettings {
         max_monsters,
         max_items,
+        depth,
     }: SpawnerSettings,
 ) {
-    let mut spawn_points: HashSet<(usize, usize)> = HashSet::new();
-    let mut item_points: HashSet<(usize, usize)> = HashSet::new();
+    // Vecs rather than sets, the order things get spawned in has to be the same for the same seed
+    let mut spawn_points: Vec<(i32, i32)> = Vec::new();
+    let mut item_points: Vec<(i32, i32)> = Vec::new();
 
-    //TODO i can make this so much better by instead generating an infinite stream of valid spawn points, and then taking the right amount for each type
     {
-        let mut rng = ecs.write_resource::<RngResource>();
+        let mut rng = ecs.write_resource::<SpawnRng>();
         let n_monsters = rng.between(0, max_monsters);
         let n_items = rng.between(0, max_items);
 
+        // whats left of the region, so we never pick the same tile twice
+        let mut free: Vec<(i32, i32)> = region.to_vec();
         for _i in 0..n_monsters {
-            let mut added = false;
-            while !added {
-                let xy = (
-                    rng.between(room.x1 + 1, room.x2) as usize,
-                    rng.between(room.y1 + 1, room.y2) as usize,
-                );
-
-                if !spawn_points.contains(&xy) {
-                    spawn_points.insert(xy);
-                    added = true;
-                }
+            if free.is_empty() {
+                break;
             }
+            let idx = rng.between(0, free.len() as i32) as usize;
+            spawn_points.push(free.swap_remove(idx));
         }
-
         for _i in 0..n_items {
-            let mut added = false;
-            while !added {
-                let xy = (
-                    rng.between(room.x1 + 1, room.x2) as usize,
-                    rng.between(room.y1 + 1, room.y2) as usize,
-                );
-                if !item_points.contains(&xy) && !spawn_points.contains(&xy) {
-                    item_points.insert(xy);
-                    added = true;
-                }
+            if free.is_empty() {
+                break;
             }
+            let idx = rng.between(0, free.len() as i32) as usize;
+            item_points.push(free.swap_remove(idx));
         }
     }
 
     for (x, y) in spawn_points.iter() {
-        random_monster(ecs, *x as i32, *y as i32);
+        random_monster(ecs, *x, *y, depth);
     }
 
     for (x, y) in item_points.iter() {
-        random_item(ecs, *x as i32, *y as i32);
+        random_item(ecs, *x, *y, depth);
     }
 }
 // pub fn entity(ecs: &mut World, ent: &Entity, pos_x: i32, pos_y: i32)
 
-pub fn random_monster(ecs: &mut World, x: i32, y: i32) {
-    let roll: i32 = {
-        let mut rng = ecs.write_resource::<RngResource>();
-        rng.between(0, 2)
+/// Rolls on the monster spawn table for this depth.
+pub fn random_monster(ecs: &mut World, x: i32, y: i32, depth: i32) {
+    let name = {
+        let table = ecs.fetch::<RawMaster>().monster_table(depth);
+        let mut rng = ecs.write_resource::<SpawnRng>();
+        table.roll(&mut rng.0).map(|n| n.to_string())
     };
 
-    if roll == 1 {
-        orc(ecs, x, y);
-    } else {
-        goblin(ecs, x, y);
+    if let Some(name) = name {
+        spawn_named(ecs, &name, x, y, depth);
     }
 }
 
-pub fn orc(ecs: &mut World, x: i32, y: i32) {
-    monster(ecs, x, y, 'o', "Orc")
+/// Rolls on the item spawn table for this depth.
+fn random_item(ecs: &mut World, x: i32, y: i32, depth: i32) {
+    let name = {
+        let table = ecs.fetch::<RawMaster>().item_table(depth);
+        let mut rng = ecs.write_resource::<SpawnRng>();
+        table.roll(&mut rng.0).map(|n| n.to_string())
+    };
+
+    if let Some(name) = name {
+        spawn_named(ecs, &name, x, y, depth);
+    }
 }
 
-pub fn goblin(ecs: &mut World, x: i32, y: i32) {
-    monster(ecs, x, y, 'g', "Goblin")
+/// Builds whatever the raws call `name` at the given spot, monster or item.
+/// Returns None if the raws dont know about it.
+pub fn spawn_named(ecs: &mut World, name: &str, x: i32, y: i32, depth: i32) -> Option<Entity> {
+    let (monster, item) = {
+        let raws = ecs.fetch::<RawMaster>();
+        (raws.monster(name).cloned(), raws.item(name).cloned())
+    };
+
+    if let Some(template) = monster {
+        return Some(monster_from_raw(ecs, &template, x, y, depth));
+    }
+    if let Some(template) = item {
+        return Some(item_from_raw(ecs, &template, x, y));
+    }
+    warn!("Nothing called {} in the raws", name);
+    None
 }
 
-fn monster<S: ToString>(ecs: &mut World, x: i32, y: i32, glyph: char, name: S) {
-    ecs.create_entity()
+/// Monsters toughen up the further down they live.
+fn monster_from_raw(ecs: &mut World, raw: &MonsterRaw, x: i32, y: i32, depth: i32) -> Entity {
+    let level = depth - 1;
+    let hp = raw.stats.max_hp + level * 2;
+    let ranged = raw.ranged.as_ref().map(|ranged| RangedAttack {
+        ability: ability_from_raw(ecs, ranged),
+        cooldown: ranged.cooldown,
+        recharge: 0,
+        keep_distance: ranged.keep_distance,
+    });
+
+    let mut builder = ecs
+        .create_entity()
         .with(Viewshed {
             visible_tiles: HashSet::new(),
-            range: 8,
+            range: raw.vision,
             dirty: true,
         })
         .with(Position { x, y })
         .with(Monster {})
         .with(Name {
-            name: name.to_string(),
-        })
-        .with(draw::Renderable {
-            glyph: rltk::to_cp437(glyph),
-            fg: RGB::named(rltk::RED),
-            bg: RGB::named(rltk::BLACK),
-            order: 1,
+            name: raw.name.clone(),
         })
+        .with(renderable(&raw.renderable))
         .with(BlocksTile {})
         .with(CombatStats {
-            max_hp: 16,
-            hp: 16,
-            power: 4,
-            defense: 1,
+            max_hp: hp,
+            hp,
+            power: raw.stats.power + level / 2,
+            defense: raw.stats.defense + level / 3,
+            damage: raw.stats.damage().plus(level / 3),
         })
-        .build();
-}
-
-fn random_item(ecs: &mut World, x: i32, y: i32) {
-    let roll: i32 = {
-        let mut rng = ecs.write_resource::<RngResource>();
-        rng.between(0, 2)
-    };
-
-    match roll {
-        1 => {health_potion(ecs, x, y)}
-        _ => {magic_missile_scroll(ecs, x, y)}
+        .with(Energy {
+            speed: raw.speed,
+            energy: 0,
+        })
+        .with(Temperament {
+            flee_below: raw.temperament.flee_below,
+            wanders: raw.temperament.wanders,
+            search_turns: raw.temperament.search_turns,
+        })
+        .with(AiState {
+            mode: AiMode::Wander,
+            last_seen: None,
+        })
+        .with(Faction {
+            name: raw.faction.clone().unwrap_or_else(|| DEFAULT_FACTION.into()),
+        });
+    if let Some(ranged) = ranged {
+        builder = builder.with(ranged);
     }
+    builder.marked::<SimpleMarker<SerializeMe>>().build()
 }
 
-fn health_potion(ecs: &mut World, x: i32, y: i32) {
-    ecs.create_entity()
-        .with(Position { x, y })
-        .with(draw::Renderable {
-            glyph: rltk::to_cp437('¡'),
-            fg: RGB::named(rltk::MAGENTA),
-            bg: RGB::named(rltk::BLACK),
-            order: 2,
-        })
+/// The entity behind a monster's ranged attack, it has what a ranged item
+/// would have but never shows up on the map or in a backpack.
+fn ability_from_raw(ecs: &mut World, raw: &RangedAttackRaw) -> Entity {
+    let mut builder = ecs
+        .create_entity()
         .with(Name {
-            name: "Health Potion".into(),
+            name: raw.name.clone(),
         })
-        .with(Item {})
-        .with(Consumable {})
-        .with(ProvidesHealing { heal_amount: 8 })
-        .build();
+        .with(Ranged { range: raw.range });
+    if let Some(damage) = raw.inflicts_damage {
+        builder = builder.with(InflictsDamage { damage });
+    }
+    if let Some(status) = &raw.status {
+        builder = builder.with(GrantsStatus {
+            kind: status.kind,
+            turns: status.turns,
+        });
+    }
+    builder.marked::<SimpleMarker<SerializeMe>>().build()
 }
 
-fn magic_missile_scroll(ecs: &mut World, x: i32, y: i32) {
-    ecs.create_entity()
+fn item_from_raw(ecs: &mut World, raw: &ItemRaw, x: i32, y: i32) -> Entity {
+    let mut builder = ecs
+        .create_entity()
         .with(Position { x, y })
-        .with(draw::Renderable {
-            glyph: rltk::to_cp437(')'),
-            fg: RGB::named(rltk::CYAN),
-            bg: RGB::named(rltk::BLACK),
-            order: 2,
-        })
+        .with(renderable(&raw.renderable))
         .with(Name {
-            name: "Magic Missile Scroll".into(),
+            name: raw.name.clone(),
         })
-        .with(Item {})
-        .with(Ranged{ range: 6})
-        .with(Consumable {})
-        .with(InflictsDamage { damage: 8 })
-        .build();
+        .with(Item {});
+
+    if raw.consumable {
+        builder = builder.with(Consumable {});
+    }
+    let effects = &raw.effects;
+    if let Some(heal_amount) = effects.provides_healing {
+        builder = builder.with(ProvidesHealing { heal_amount });
+    }
+    if let Some(range) = effects.ranged {
+        builder = builder.with(Ranged { range });
+    }
+    if let Some(damage) = effects.inflicts_damage {
+        builder = builder.with(InflictsDamage { damage });
+    }
+    if let Some(radius) = effects.area_of_effect {
+        builder = builder.with(AreaOfEffect { radius });
+    }
+    if let Some(status) = &effects.status {
+        builder = builder.with(GrantsStatus {
+            kind: status.kind,
+            turns: status.turns,
+        });
+    }
+    if let Some(equippable) = &raw.equippable {
+        builder = builder.with(Equippable {
+            slot: equippable.slot,
+        });
+        if equippable.power_bonus != 0 {
+            builder = builder.with(MeleePowerBonus {
+                power: equippable.power_bonus,
+            });
+        }
+        if let Some(damage) = equippable.damage() {
+            builder = builder.with(MeleeDamage { damage });
+        }
+        if equippable.defense_bonus != 0 {
+            builder = builder.with(DefenseBonus {
+                defense: equippable.defense_bonus,
+            });
+        }
+    }
+
+    builder.marked::<SimpleMarker<SerializeMe>>().build()
+}
+
+fn renderable(raw: &RenderableRaw) -> draw::Renderable {
+    draw::Renderable {
+        glyph: raw.glyph(),
+        fg: raw.fg(),
+        bg: raw.bg(),
+        order: raw.order,
+    }
 }
 
 pub struct SpawnerSettings {
     pub max_monsters: i32,
     pub max_items: i32,
+    pub depth: i32,
+}
+
+impl SpawnerSettings {
+    /// Deeper levels get more crowded.
+    pub fn for_depth(depth: i32) -> Self {
+        SpawnerSettings {
+            max_monsters: 4 + (depth - 1) / 2,
+            depth,
+            ..SpawnerSettings::default()
+        }
+    }
 }
 
 impl Default for SpawnerSettings {
@@ -202,6 +302,7 @@ impl Default for SpawnerSettings {
         SpawnerSettings {
             max_monsters: 4,
             max_items: 4,
+            depth: 1,
         }
     }
 }
@@ -1,11 +1,13 @@
+use crate::camera::Camera;
 use crate::components::*;
 use crate::systems::*;
 use rltk::SmallVec;
 use rltk::{field_of_view, Algorithm2D, BaseMap, Point, Rltk, RGB};
+use serde::{Deserialize, Serialize};
 use specs::prelude::*;
 use specs_derive::Component;
 
-#[derive(Component)]
+#[derive(Component, Serialize, Deserialize, Clone)]
 pub struct Renderable {
     pub glyph: rltk::FontCharType,
     pub fg: RGB,
@@ -21,7 +23,7 @@ impl Algorithm2D for map::TetraMap {
 
 impl BaseMap for map::TetraMap {
     fn is_opaque(&self, idx: usize) -> bool {
-        self.buffer.data[idx as usize] == map::TileType::Wall
+        self.buffer.data[idx].is_opaque()
     }
 
     fn get_pathing_distance(&self, idx1: usize, idx2: usize) -> f32 {
@@ -32,40 +34,54 @@ impl BaseMap for map::TetraMap {
     }
 
     fn get_available_exits(&self, idx: usize) -> SmallVec<[(usize, f32); 10]> {
-        let mut exits = SmallVec::<[(usize, f32); 10]>::new();
-
-        let x = idx as i32 % self.width();
-        let y = idx as i32 / self.width();
-        let w = self.width() as usize;
+        exits(self, idx, |x, y| is_exit_valid(self, x, y))
+    }
+}
 
-        if is_exit_valid(self, x - 1, y) {
-            exits.push((idx - 1, 1.0))
-        };
-        if is_exit_valid(self, x + 1, y) {
-            exits.push((idx + 1, 1.0))
-        };
-        if is_exit_valid(self, x, y - 1) {
-            exits.push((idx - w, 1.0))
-        };
-        if is_exit_valid(self, x, y + 1) {
-            exits.push((idx + w, 1.0))
-        };
-
-        if is_exit_valid(self, x - 1, y - 1) {
-            exits.push(((idx - w) - 1, 1.45))
-        };
-        if is_exit_valid(self, x + 1, y - 1) {
-            exits.push(((idx - w) + 1, 1.45))
-        };
-        if is_exit_valid(self, x - 1, y + 1) {
-            exits.push(((idx + w) - 1, 1.45))
-        };
-        if is_exit_valid(self, x + 1, y + 1) {
-            exits.push(((idx + w) + 1, 1.45))
-        };
-        // info!("{:?}", exits);
-        exits
+/// The tiles you can step to from `idx` and what each step costs, `passable`
+/// says which tiles are fair game.
+pub fn exits<F>(map: &map::TetraMap, idx: usize, passable: F) -> SmallVec<[(usize, f32); 10]>
+where
+    F: Fn(i32, i32) -> bool,
+{
+    let mut exits = SmallVec::<[(usize, f32); 10]>::new();
+
+    let x = idx as i32 % map.width();
+    let y = idx as i32 / map.width();
+    let w = map.width() as usize;
+
+    if passable(x - 1, y) {
+        exits.push((idx - 1, 1.0))
+    };
+    if passable(x + 1, y) {
+        exits.push((idx + 1, 1.0))
+    };
+    if passable(x, y - 1) {
+        exits.push((idx - w, 1.0))
+    };
+    if passable(x, y + 1) {
+        exits.push((idx + w, 1.0))
+    };
+
+    if passable(x - 1, y - 1) {
+        exits.push(((idx - w) - 1, 1.45))
+    };
+    if passable(x + 1, y - 1) {
+        exits.push(((idx - w) + 1, 1.45))
+    };
+    if passable(x - 1, y + 1) {
+        exits.push(((idx + w) - 1, 1.45))
+    };
+    if passable(x + 1, y + 1) {
+        exits.push(((idx + w) + 1, 1.45))
+    };
+    // going through a closed door costs the extra turn it takes to open it
+    for (idx, cost) in exits.iter_mut() {
+        if map.buffer.data[*idx] == map::TileType::DoorClosed {
+            *cost += 1.0;
+        }
     }
+    exits
 }
 
 fn is_exit_valid(map: &map::TetraMap, x: i32, y: i32) -> bool {
@@ -80,7 +96,7 @@ fn is_exit_valid(map: &map::TetraMap, x: i32, y: i32) -> bool {
 impl FogOfWarAlgorithm for VisibilitySystem {
     fn generate_viewshed(map: &map::TetraMap, viewshed: &mut Viewshed, position: &Position) {
         viewshed.visible_tiles =
-            field_of_view(Point::new(position.x, position.y), viewshed.range, &*map)
+            field_of_view(Point::new(position.x, position.y), viewshed.range, map)
                 .iter()
                 .filter(|p| p.x >= 0 && p.x < map.width() && p.y >= 0 && p.y < map.height())
                 .map(|p| map.buffer.xy_idx(p.x, p.y))
@@ -94,20 +110,25 @@ impl FogOfWarAlgorithm for VisibilitySystem {
     }
 }
 
-pub fn draw_map(ecs: &World, ctx: &mut Rltk) {
+pub fn draw_map(ecs: &World, ctx: &mut Rltk, camera: &Camera) {
     let map = ecs.fetch::<map::TetraMap>();
     let players = ecs.read_storage::<Player>();
     let viewsheds = ecs.read_storage::<Viewshed>();
 
     for (player, viewshed) in (&players, &viewsheds).join() {
-        let mut x = 0;
-        let mut y = 0;
-        for (idx, tile) in map.buffer.data.iter().enumerate() {
-            let _pt = Point::new(x, y);
-            if player.revealed_tiles.contains(&idx) {
+        for sy in 0..camera.height {
+            for sx in 0..camera.width {
+                let (x, y) = match camera.to_world(sx, sy) {
+                    Some((x, y)) if x < map.width() && y < map.height() => (x, y),
+                    _ => continue,
+                };
+                let idx = map.xy_idx(x, y);
+                if !player.revealed_tiles.contains(&idx) {
+                    continue;
+                }
                 let glyph;
                 let mut fg;
-                match tile {
+                match map.buffer.data[idx] {
                     map::TileType::Floor => {
                         fg = RGB::from_f32(0.35, 0.5, 0.5);
                         glyph = rltk::to_cp437('.');
@@ -116,20 +137,45 @@ pub fn draw_map(ecs: &World, ctx: &mut Rltk) {
                         fg = RGB::from_f32(0.0, 1.0, 0.0);
                         glyph = rltk::to_cp437('#');
                     }
+                    map::TileType::DownStairs => {
+                        fg = RGB::from_f32(0.0, 1.0, 1.0);
+                        glyph = rltk::to_cp437('>');
+                    }
+                    map::TileType::UpStairs => {
+                        fg = RGB::from_f32(0.0, 1.0, 1.0);
+                        glyph = rltk::to_cp437('<');
+                    }
+                    map::TileType::DoorClosed => {
+                        fg = RGB::from_f32(0.6, 0.4, 0.2);
+                        glyph = rltk::to_cp437('+');
+                    }
+                    map::TileType::DoorOpen => {
+                        fg = RGB::from_f32(0.6, 0.4, 0.2);
+                        glyph = rltk::to_cp437('/');
+                    }
                 }
 
                 if !viewshed.visible_tiles.contains(&idx) {
                     fg = fg.to_greyscale();
                 }
 
-                ctx.set(x, y, fg, RGB::from_f32(0., 0., 0.), glyph);
+                ctx.set(sx, sy, fg, RGB::from_f32(0., 0., 0.), glyph);
             }
+        }
+    }
+}
 
-            x += 1;
-            if x > 79 {
-                x = 0;
-                y += 1;
-            }
+/// Everything with a position and a look, moved into screen space.
+pub fn draw_entities(ecs: &World, ctx: &mut Rltk, camera: &Camera) {
+    let positions = ecs.read_storage::<Position>();
+    let renderables = ecs.read_storage::<Renderable>();
+    //TODO only draw when inside of the players viewshed.
+    let mut data: Vec<_> = (&positions, &renderables).join().collect();
+    data.sort_by_key(|(_, k)| -k.order);
+
+    for (pos, render) in data.iter() {
+        if let Some((sx, sy)) = camera.to_screen(pos.x, pos.y) {
+            ctx.set(sx, sy, render.fg, render.bg, render.glyph);
         }
     }
 }
@@ -1,5 +1,7 @@
+use crate::camera::Camera;
 use crate::components::{map::TetraMap, *};
 use rltk::{Point, Rltk, VirtualKeyCode, RGB};
+use serde::{Deserialize, Serialize};
 use specs::prelude::*;
 
 pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
@@ -11,6 +13,15 @@ pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
         RGB::named(rltk::WHITE),
         RGB::named(rltk::BLACK),
     );
+    let depth = ecs.fetch::<Depth>();
+    ctx.print_color(
+        2,
+        43,
+        RGB::named(rltk::YELLOW),
+        RGB::named(rltk::BLACK),
+        format!("Depth: {}", depth.0),
+    );
+
     let combat_stats = ecs.read_storage::<CombatStats>();
     let players = ecs.read_storage::<Player>();
 
@@ -34,6 +45,17 @@ pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
         );
     }
 
+    let statuses = ecs.read_storage::<StatusEffects>();
+    let player = *ecs.fetch::<Entity>();
+    if let Some(status) = statuses.get(player) {
+        let mut x = 2;
+        for effect in status.effects.iter() {
+            let text = format!("{} ({})", effect.kind.describe(), effect.turns);
+            ctx.print_color(x, 49, RGB::named(rltk::CYAN), RGB::named(rltk::BLACK), &text);
+            x += text.len() as i32 + 2;
+        }
+    }
+
     let log = ecs.fetch::<GameLog>();
     let y = 44; // FIXME should definitely not be hardcoded.
     for (x, s) in log.entries.iter().rev().enumerate().take(5) {
@@ -44,19 +66,19 @@ pub fn draw_ui(ecs: &World, ctx: &mut Rltk) {
     ctx.set_bg(mouse_pos.0, mouse_pos.1, RGB::named(rltk::MAGENTA));
 }
 
-pub fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
+pub fn draw_tooltips(ecs: &World, ctx: &mut Rltk, camera: &Camera) {
     let map = ecs.fetch::<TetraMap>();
     let names = ecs.read_storage::<Name>();
     let positions = ecs.read_storage::<Position>();
 
     let (mx, my) = ctx.mouse_pos();
-    // FIXME this is a prime example of where NaN poisoning can come in
-    if mx >= map.width() || my >= map.height() {
-        return;
-    }
+    let (wx, wy) = match camera.to_world(mx, my) {
+        Some((wx, wy)) if wx < map.width() && wy < map.height() => (wx, wy),
+        _ => return,
+    };
     let mut tooltip: Vec<String> = Vec::new();
     for (name, pos) in (&names, &positions).join() {
-        if pos.x == mx && pos.y == my {
+        if pos.x == wx && pos.y == wy {
             tooltip.push(name.name.clone());
         }
     }
@@ -73,8 +95,7 @@ pub fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
         if mx > 40 {
             let arrow_pos = Point::new(mx - 2, my);
             let left_x = mx - width;
-            let mut y = my;
-            for s in tooltip.iter() {
+            for (y, s) in (my..).zip(tooltip.iter()) {
                 ctx.print_color(
                     left_x,
                     y,
@@ -89,23 +110,21 @@ pub fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
                         y,
                         RGB::named(rltk::WHITE),
                         RGB::named(rltk::GREY),
-                        &" ".to_string(),
+                        " ",
                     );
                 }
-                y += 1;
             }
             ctx.print_color(
                 arrow_pos.x,
                 arrow_pos.y,
                 RGB::named(rltk::WHITE),
                 RGB::named(rltk::GREY),
-                &"->".to_string(),
+                "->",
             );
         } else {
             let arrow_pos = Point::new(mx + 1, my);
             let left_x = mx + 3;
-            let mut y = my;
-            for s in tooltip.iter() {
+            for (y, s) in (my..).zip(tooltip.iter()) {
                 ctx.print_color(
                     left_x + 1,
                     y,
@@ -120,17 +139,16 @@ pub fn draw_tooltips(ecs: &World, ctx: &mut Rltk) {
                         y,
                         RGB::named(rltk::WHITE),
                         RGB::named(rltk::GREY),
-                        &" ".to_string(),
+                        " ",
                     );
                 }
-                y += 1;
             }
             ctx.print_color(
                 arrow_pos.x,
                 arrow_pos.y,
                 RGB::named(rltk::WHITE),
                 RGB::named(rltk::GREY),
-                &"<-".to_string(),
+                "<-",
             );
         }
     }
@@ -143,15 +161,16 @@ pub enum ItemMenuResult<T> {
     Selected { item: T },
 }
 
-pub fn inventory_menu_input(ctx: &mut Rltk, items: Vec<(Name, Entity)>) -> ItemMenuResult<Entity> {
+/// Picks an item out of a menu of `count` entries, handing back its index.
+pub fn inventory_menu_input(ctx: &mut Rltk, count: usize) -> ItemMenuResult<usize> {
     match ctx.key {
         None => ItemMenuResult::NoResponse,
         Some(VirtualKeyCode::Escape) => ItemMenuResult::Cancel,
         Some(key) => {
             let selection = rltk::letter_to_option(key);
-            if selection > -1 && selection < items.len() as i32 {
+            if selection > -1 && selection < count as i32 {
                 ItemMenuResult::Selected {
-                    item: items[selection as usize].1,
+                    item: selection as usize,
                 }
             } else {
                 ItemMenuResult::NoResponse
@@ -163,9 +182,9 @@ pub fn inventory_menu_input(ctx: &mut Rltk, items: Vec<(Name, Entity)>) -> ItemM
 // y = 25
 // x = 15
 
-pub fn draw_inventory_screen(ctx: &mut Rltk, x: i32, y: i32, title: &String, items: &[&String]) {
+pub fn draw_inventory_screen(ctx: &mut Rltk, x: i32, y: i32, title: &str, items: &[&String]) {
     let count = items.len() as i32;
-    let mut y = y - (count / 2);
+    let y = y - (count / 2);
     let items: Vec<_> = items
         .iter()
         .enumerate()
@@ -175,7 +194,7 @@ pub fn draw_inventory_screen(ctx: &mut Rltk, x: i32, y: i32, title: &String, ite
         x,
         y - 2,
         31,
-        (count + 3) as i32,
+        count + 3,
         RGB::named(rltk::WHITE),
         RGB::named(rltk::BLACK),
     );
@@ -188,13 +207,13 @@ pub fn draw_inventory_screen(ctx: &mut Rltk, x: i32, y: i32, title: &String, ite
     );
     ctx.print_color(
         x + 3,
-        y + count as i32 + 1,
+        y + count + 1,
         RGB::named(rltk::YELLOW),
         RGB::named(rltk::BLACK),
         "Press ESC to cancel".to_string(),
     );
 
-    for (i, name) in items.iter() {
+    for (y, (i, name)) in (y..).zip(items.iter()) {
         ctx.set(
             x + 2,
             y,
@@ -218,11 +237,19 @@ pub fn draw_inventory_screen(ctx: &mut Rltk, x: i32, y: i32, title: &String, ite
         );
 
         ctx.print(x + 6, y, name);
-        y += 1;
     }
 }
 
-pub fn ranged_target(ecs: &mut World, ctx: &mut Rltk, range: i32) -> ItemMenuResult<(i32, i32)> {
+/// Pick a visible tile within range with the mouse. With a radius above zero
+/// the tiles the blast would reach get shown around the cursor. The picked
+/// tile is in map coordinates.
+pub fn ranged_target(
+    ecs: &mut World,
+    ctx: &mut Rltk,
+    camera: &Camera,
+    range: i32,
+    radius: i32,
+) -> ItemMenuResult<(i32, i32)> {
     let player = ecs.fetch::<Entity>();
     let positions = ecs.read_storage::<Position>();
     let player_pos = positions.get(*player).unwrap();
@@ -250,7 +277,9 @@ pub fn ranged_target(ecs: &mut World, ctx: &mut Rltk, range: i32) -> ItemMenuRes
                 Point { x, y },
             );
             if distance <= range as f32 {
-                ctx.set_bg(x, y, RGB::named(rltk::BLUE));
+                if let Some((sx, sy)) = camera.to_screen(x, y) {
+                    ctx.set_bg(sx, sy, RGB::named(rltk::BLUE));
+                }
                 available_cells.push(idx);
             }
         }
@@ -259,30 +288,142 @@ pub fn ranged_target(ecs: &mut World, ctx: &mut Rltk, range: i32) -> ItemMenuRes
     }
 
     let mouse_pos = ctx.mouse_pos();
+    let mouse_world = camera.to_world(mouse_pos.0, mouse_pos.1);
     let target = available_cells
         .iter()
         .map(|x| map.xy(**x))
-        .find(|p| p == &mouse_pos);
+        .find(|p| Some(*p) == mouse_world);
 
     let res = target.map_or_else(
         || ItemMenuResult::NoResponse,
         |(x, y)| ItemMenuResult::Selected { item: (x, y) },
     );
 
+    if let ItemMenuResult::Selected { item: (x, y) } = res {
+        if radius > 0 {
+            for (bx, by) in map.blast_area(x, y, radius) {
+                if let Some((sx, sy)) = camera.to_screen(bx, by) {
+                    ctx.set_bg(sx, sy, RGB::named(rltk::ORANGE));
+                }
+            }
+        }
+    }
+
     match res {
         ItemMenuResult::NoResponse if ctx.left_click => {
             ctx.set_bg(mouse_pos.0, mouse_pos.1, RGB::named(rltk::RED));
             ItemMenuResult::Cancel
         }
-        ItemMenuResult::Selected { item: (x, y) } if ctx.left_click => {
-            ctx.set_bg(x, y, RGB::named(rltk::CYAN2));
+        ItemMenuResult::Selected { .. } if ctx.left_click => {
+            ctx.set_bg(mouse_pos.0, mouse_pos.1, RGB::named(rltk::CYAN2));
             res
         }
-        ItemMenuResult::Selected { item: (x, y) } => {
-            ctx.set_bg(x, y, RGB::named(rltk::CYAN2));
+        ItemMenuResult::Selected { .. } => {
+            ctx.set_bg(mouse_pos.0, mouse_pos.1, RGB::named(rltk::CYAN2));
             ItemMenuResult::NoResponse
         }
         _ => ItemMenuResult::NoResponse
 
     }
 }
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum MainMenuSelection {
+    NewGame,
+    Continue,
+    Quit,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum PauseMenuSelection {
+    Resume,
+    SaveAndQuit,
+}
+
+#[derive(PartialEq, Copy, Clone)]
+pub enum MenuResult<T> {
+    NoSelection { selected: T },
+    Selected { selected: T },
+}
+
+/// Lists the options centered on the screen from row `y` down, the arrow keys
+/// move the highlight and enter picks it.
+fn menu<T: Copy + PartialEq>(ctx: &mut Rltk, y: i32, options: &[(T, &str)], selected: T) -> MenuResult<T> {
+    for (row, (option, label)) in (y..).zip(options.iter()) {
+        let fg = if *option == selected {
+            RGB::named(rltk::MAGENTA)
+        } else {
+            RGB::named(rltk::WHITE)
+        };
+        ctx.print_color_centered(row, fg, RGB::named(rltk::BLACK), label);
+    }
+
+    let current = options.iter().position(|(o, _)| *o == selected).unwrap_or(0);
+    match ctx.key {
+        Some(VirtualKeyCode::Up) | Some(VirtualKeyCode::K) => MenuResult::NoSelection {
+            selected: options[(current + options.len() - 1) % options.len()].0,
+        },
+        Some(VirtualKeyCode::Down) | Some(VirtualKeyCode::J) => MenuResult::NoSelection {
+            selected: options[(current + 1) % options.len()].0,
+        },
+        Some(VirtualKeyCode::Return) => MenuResult::Selected { selected },
+        _ => MenuResult::NoSelection { selected },
+    }
+}
+
+/// Continue only shows up when there is a save to continue from.
+pub fn main_menu(ctx: &mut Rltk, selected: MainMenuSelection, can_continue: bool) -> MenuResult<MainMenuSelection> {
+    ctx.print_color_centered(15, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Tetra");
+
+    let mut options = vec![(MainMenuSelection::NewGame, "Begin New Game")];
+    let mut selected = selected;
+    if can_continue {
+        options.push((MainMenuSelection::Continue, "Continue"));
+    } else if selected == MainMenuSelection::Continue {
+        selected = MainMenuSelection::NewGame;
+    }
+    options.push((MainMenuSelection::Quit, "Quit"));
+    menu(ctx, 24, &options, selected)
+}
+
+pub fn pause_menu(ctx: &mut Rltk, selected: PauseMenuSelection) -> MenuResult<PauseMenuSelection> {
+    ctx.draw_box(30, 20, 20, 6, RGB::named(rltk::WHITE), RGB::named(rltk::BLACK));
+    ctx.print_color_centered(20, RGB::named(rltk::YELLOW), RGB::named(rltk::BLACK), "Paused");
+
+    let options = [
+        (PauseMenuSelection::Resume, "Resume"),
+        (PauseMenuSelection::SaveAndQuit, "Save and Quit"),
+    ];
+    if ctx.key == Some(VirtualKeyCode::Escape) {
+        return MenuResult::Selected {
+            selected: PauseMenuSelection::Resume,
+        };
+    }
+    menu(ctx, 22, &options, selected)
+}
+
+/// True once the player has seen enough of it and pressed a key.
+pub fn game_over(ecs: &World, ctx: &mut Rltk) -> bool {
+    ctx.print_color_centered(15, RGB::named(rltk::RED), RGB::named(rltk::BLACK), "Your journey has ended!");
+    ctx.print_color_centered(
+        17,
+        RGB::named(rltk::WHITE),
+        RGB::named(rltk::BLACK),
+        format!(
+            "You made it to depth {} in {} turns.",
+            ecs.fetch::<Depth>().0,
+            ecs.fetch::<Turn>().0
+        ),
+    );
+    let log = ecs.fetch::<GameLog>();
+    for (row, entry) in (20..).zip(log.entries.iter().rev().take(5).collect::<Vec<_>>().into_iter().rev()) {
+        ctx.print_color_centered(row, RGB::named(rltk::GREY), RGB::named(rltk::BLACK), entry);
+    }
+    ctx.print_color_centered(
+        27,
+        RGB::named(rltk::MAGENTA),
+        RGB::named(rltk::BLACK),
+        "Press any key to return to the menu.",
+    );
+    ctx.key.is_some()
+}
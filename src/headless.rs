@@ -0,0 +1,71 @@
+use crate::components::*;
+use crate::player::Command;
+use crate::{RunState, State};
+use specs::prelude::*;
+
+/// Runs the game without an rltk window, the player is whatever feeds it commands.
+pub struct Headless {
+    pub state: State,
+}
+
+impl Headless {
+    pub fn new(seed: u64) -> Headless {
+        Headless::from_state(State::new(seed))
+    }
+
+    pub fn from_state(state: State) -> Headless {
+        let mut headless = Headless { state };
+        headless.settle();
+        headless
+    }
+
+    pub fn run_state(&self) -> RunState {
+        *self.state.ecs.fetch::<RunState>()
+    }
+
+    pub fn player_dead(&self) -> bool {
+        let player = *self.state.ecs.fetch::<Entity>();
+        self.state
+            .ecs
+            .read_storage::<CombatStats>()
+            .get(player)
+            .is_none_or(|stats| stats.hp < 1)
+    }
+
+    /// Hand the command to the player and let the world play out until its
+    /// waiting on the player again.
+    pub fn step(&mut self, command: Command) -> RunState {
+        let next = self.state.execute(command);
+        *self.state.ecs.write_resource::<RunState>() = next;
+        self.settle()
+    }
+
+    /// Feed commands until they run out or the player dies, returns how many were used.
+    pub fn run<I>(&mut self, commands: I) -> usize
+    where
+        I: IntoIterator<Item = Command>,
+    {
+        let mut used = 0;
+        for command in commands {
+            if self.player_dead() {
+                break;
+            }
+            self.step(command);
+            used += 1;
+        }
+        used
+    }
+
+    fn settle(&mut self) -> RunState {
+        loop {
+            match self.state.advance() {
+                // rounds can go by one after the other while the player is slow
+                RunState::PreRun
+                | RunState::PlayerTurn
+                | RunState::MonsterTurn
+                | RunState::NextLevel => {}
+                other => return other,
+            }
+        }
+    }
+}
@@ -0,0 +1,697 @@
+use components::*;
+use log::*;
+use player::Command;
+use rltk::{GameState, Rltk};
+use specs::{
+    error::NoError,
+    prelude::*,
+    saveload::{ConvertSaveload, Marker, SimpleMarker, SimpleMarkerAllocator},
+};
+use serde::{Deserialize, Serialize};
+use specs_derive::ConvertSaveload;
+
+extern crate log;
+extern crate specs;
+
+pub mod camera;
+pub mod components;
+pub mod draw;
+pub mod gui;
+pub mod headless;
+pub mod map_builders;
+pub mod morgue;
+pub mod player;
+pub mod raws;
+pub mod replay;
+pub mod saveload;
+pub mod systems;
+pub mod util;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// the map doesn't need to fit on the screen, the camera follows the player
+pub const MAP_WIDTH: i32 = 120;
+pub const MAP_HEIGHT: i32 = 64;
+
+pub fn register_components(ecs: &mut World) {
+    ecs.register::<Position>();
+    ecs.register::<draw::Renderable>();
+    ecs.register::<Name>();
+    ecs.register::<Viewshed>();
+    ecs.register::<Monster>();
+    ecs.register::<Player>();
+    ecs.register::<CombatStats>();
+    ecs.register::<BlocksTile>();
+    ecs.register::<WantsToMelee>();
+    ecs.register::<WantsToPickupItem>();
+    ecs.register::<SufferDamage>();
+    ecs.register::<ProvidesHealing>();
+    ecs.register::<Item>();
+    ecs.register::<InBackpack>();
+    ecs.register::<Consumable>();
+    ecs.register::<WantsToUseItem>();
+    ecs.register::<WantsToDropItem>();
+    ecs.register::<InflictsDamage>();
+    ecs.register::<Ranged>();
+    ecs.register::<AreaOfEffect>();
+    ecs.register::<Equippable>();
+    ecs.register::<Equipped>();
+    ecs.register::<MeleePowerBonus>();
+    ecs.register::<DefenseBonus>();
+    ecs.register::<WantsToRemoveItem>();
+    ecs.register::<StatusEffects>();
+    ecs.register::<GrantsStatus>();
+    ecs.register::<Energy>();
+    ecs.register::<Temperament>();
+    ecs.register::<AiState>();
+    ecs.register::<RangedAttack>();
+    ecs.register::<Faction>();
+    ecs.register::<MeleeDamage>();
+    ecs.register::<WantsToOpenDoor>();
+    ecs.register::<SimpleMarker<saveload::SerializeMe>>();
+    ecs.register::<saveload::SerializationHelper>();
+}
+
+/// (Re)seed the world off the one seed, the map and spawn streams are seeded
+/// per level when its generated.
+pub fn seed_rngs(ecs: &mut World, seed: u64) {
+    use util::random::{seeded, Stream};
+    info!("Seeding the world with {}", seed);
+    ecs.insert(util::Seed(seed));
+    ecs.insert::<util::RngResource>(seeded(seed, Stream::Combat));
+}
+
+/// Every depth gets its own map and spawn streams, so a level comes out the
+/// same no matter how we got to it, loading a save included.
+fn seed_level_rngs(ecs: &mut World, depth: i32) {
+    use util::random::{level_seed, seeded, Stream};
+    let seed = level_seed(ecs.fetch::<util::Seed>().0, depth);
+    ecs.insert(util::MapRng(seeded(seed, Stream::Map)));
+    ecs.insert(util::SpawnRng(seeded(seed, Stream::Spawn)));
+}
+
+/// Start a fresh game at the top of the dungeon, the world needs to have its
+/// components registered and rngs seeded already.
+pub fn new_game(ecs: &mut World) {
+    ecs.insert(GameLog {
+        entries: vec!["Welcome to tetra, young traveler !".to_string()],
+    });
+    ecs.insert(Turn(0));
+    ecs.insert(Depth(1));
+    ecs.insert(KilledBy(None));
+    ecs.insert(systems::FlowMaps::default());
+
+    let (x, y) = generate_level(ecs, 1);
+
+    // Player entity yay
+    let player_entity = spawner::player(ecs, x, y);
+    ecs.insert(player_entity);
+
+    ecs.insert(RunState::PreRun);
+}
+
+/// Build the map for the given depth and populate it, hands back where the
+/// player should start.
+fn generate_level(ecs: &mut World, depth: i32) -> (i32, i32) {
+    seed_level_rngs(ecs, depth);
+
+    let prefabs = ecs.fetch::<raws::RawMaster>().prefabs.clone();
+    let (
+        map_builders::BuiltMap {
+            mut map,
+            start,
+            exit,
+            spawn_regions,
+        },
+        prefab_spawns,
+        stats,
+    ) = {
+        let mut rng = ecs.write_resource::<util::MapRng>();
+        let mut builder = map_builders::builder_for_depth(depth, &mut rng);
+        info!("Building depth {} with {}", depth, builder.name());
+        // the map rng keeps going between attempts, so a rebuild still comes out the same for a seed
+        let (built, mut spawns, mut stats) = loop {
+            let mut built = match builder.build(&mut rng.0, MAP_WIDTH, MAP_HEIGHT, depth) {
+                Ok(built) => built,
+                Err(e) => {
+                    warn!("Rebuilding depth {}: {}", depth, e);
+                    continue;
+                }
+            };
+            let spawns = map_builders::prefab::stamp_prefabs(&mut built.map, &prefabs, &mut rng.0, depth);
+            match map_builders::connectivity::ensure_connected(&mut built) {
+                Ok(stats) => break (built, spawns, stats),
+                Err(e) => warn!("Rebuilding depth {}: {}", depth, e),
+            }
+        };
+        stats.builder = builder.name().to_string();
+        let reachable = map_builders::connectivity::reachable(&built.map, built.start);
+        let before = spawns.len();
+        spawns.retain(|((x, y), _)| reachable[built.map.xy_idx(*x, *y)]);
+        stats.spawns_dropped += before - spawns.len();
+        (built, spawns, stats)
+    };
+    info!("Generated depth {}: {:?}", depth, stats);
+
+    map_builders::place_doors(&mut map);
+    map.buffer.set(exit.0, exit.1, map::TileType::DownStairs);
+    if depth > 1 {
+        map.buffer.set(start.0, start.1, map::TileType::UpStairs);
+    }
+
+    for region in spawn_regions.iter() {
+        spawner::spawn_region(ecs, region, spawner::SpawnerSettings::for_depth(depth));
+    }
+    for ((x, y), name) in prefab_spawns.iter() {
+        spawner::spawn_named(ecs, name, *x, *y, depth);
+    }
+
+    ecs.insert(map);
+    start
+}
+
+/// Throw away everything on the current level except for the player and what
+/// they are carrying, and take them one level further down.
+pub fn next_level(ecs: &mut World) {
+    let player = *ecs.fetch::<Entity>();
+
+    let to_delete: Vec<Entity> = {
+        let entities = ecs.entities();
+        let backpacks = ecs.read_storage::<InBackpack>();
+        let equipped = ecs.read_storage::<Equipped>();
+        entities
+            .join()
+            .filter(|e| *e != player)
+            .filter(|e| backpacks.get(*e).is_none_or(|b| b.owner != player))
+            .filter(|e| equipped.get(*e).is_none_or(|eq| eq.owner != player))
+            .collect()
+    };
+    for e in to_delete {
+        ecs.delete_entity(e).expect("Unable to delete entity");
+    }
+
+    let depth = {
+        let mut depth = ecs.write_resource::<Depth>();
+        depth.0 += 1;
+        depth.0
+    };
+
+    let (x, y) = generate_level(ecs, depth);
+
+    ecs.write_storage::<Position>()
+        .insert(player, Position { x, y })
+        .expect("Unable to move the player");
+    if let Some(viewshed) = ecs.write_storage::<Viewshed>().get_mut(player) {
+        viewshed.dirty = true;
+    }
+    if let Some(player) = ecs.write_storage::<Player>().get_mut(player) {
+        player.revealed_tiles.clear();
+    }
+
+    ecs.write_resource::<GameLog>()
+        .say(format!("You descend to depth {}.", depth));
+}
+
+/// True if the player has saved up enough energy to take their next action.
+pub fn player_ready(ecs: &World) -> bool {
+    let player = *ecs.fetch::<Entity>();
+    ecs.read_storage::<Energy>()
+        .get(player)
+        .is_none_or(|e| e.ready())
+}
+
+/// True if the player is under the given status effect.
+pub fn player_has(ecs: &World, kind: StatusKind) -> bool {
+    let player = *ecs.fetch::<Entity>();
+    ecs.read_storage::<StatusEffects>()
+        .get(player)
+        .is_some_and(|s| s.has(kind))
+}
+
+pub struct State {
+    pub ecs: World,
+    pub recorder: Option<replay::Recorder>,
+    /// The seed given on the command line, every new game starts from it
+    /// instead of a random one.
+    pub fixed_seed: Option<u64>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State::new(util::random::random_seed())
+    }
+}
+
+impl GameState for State {
+    fn tick(&mut self, ctx: &mut Rltk) {
+        ctx.cls();
+
+        let mut newrunstate = *self.ecs.fetch::<RunState>();
+
+        let camera = camera::Camera::new(&self.ecs, ctx);
+        match newrunstate {
+            RunState::MainMenu(_) | RunState::GameOver => {}
+            _ => {
+                draw::draw_map(&self.ecs, ctx, &camera);
+                draw::draw_entities(&self.ecs, ctx, &camera);
+                gui::draw_ui(&self.ecs, ctx);
+                gui::draw_tooltips(&self.ecs, ctx, &camera);
+            }
+        }
+
+        match newrunstate {
+            RunState::MainMenu(selected) => {
+                match gui::main_menu(ctx, selected, saveload::does_save_exist()) {
+                    gui::MenuResult::NoSelection { selected } => {
+                        newrunstate = RunState::MainMenu(selected)
+                    }
+                    gui::MenuResult::Selected { selected } => match selected {
+                        // the world behind the menu is already a fresh game
+                        gui::MainMenuSelection::NewGame => newrunstate = RunState::PreRun,
+                        gui::MainMenuSelection::Continue => newrunstate = RunState::LoadGame,
+                        gui::MainMenuSelection::Quit => ctx.quit(),
+                    },
+                }
+            }
+            RunState::PauseMenu(selected) => match gui::pause_menu(ctx, selected) {
+                gui::MenuResult::NoSelection { selected } => {
+                    newrunstate = RunState::PauseMenu(selected)
+                }
+                gui::MenuResult::Selected { selected } => match selected {
+                    gui::PauseMenuSelection::Resume => newrunstate = RunState::AwaitingInput,
+                    gui::PauseMenuSelection::SaveAndQuit => {
+                        newrunstate = if self.save_game() {
+                            self.restart();
+                            RunState::MainMenu(gui::MainMenuSelection::Continue)
+                        } else {
+                            RunState::AwaitingInput
+                        };
+                    }
+                },
+            },
+            RunState::GameOver => {
+                if gui::game_over(&self.ecs, ctx) {
+                    self.restart();
+                    newrunstate = RunState::MainMenu(gui::MainMenuSelection::NewGame);
+                }
+            }
+            RunState::PreRun
+            | RunState::PlayerTurn
+            | RunState::MonsterTurn
+            | RunState::NextLevel => {
+                newrunstate = self.advance();
+                if newrunstate == RunState::GameOver {
+                    match morgue::write_morgue(&self.ecs) {
+                        Ok(path) => info!("Wrote the morgue to {}", path.display()),
+                        Err(e) => error!("Unable to write the morgue: {}", e),
+                    }
+                    // dead is dead, no loading back in to before it happened
+                    if let Err(e) = saveload::delete_save() {
+                        error!("Unable to delete the save: {}", e);
+                    }
+                }
+            }
+            RunState::AwaitingInput => {
+                newrunstate = player::player_input(self, ctx);
+            }
+            RunState::InventoryScreen => {
+                match display_inventory_selection(ctx, &mut self.ecs, "Use Item") {
+                    gui::ItemMenuResult::Cancel => newrunstate = RunState::AwaitingInput,
+                    gui::ItemMenuResult::NoResponse => {}
+                    gui::ItemMenuResult::Selected { item: (slot, item) } => {
+                        let range = self.ecs.read_storage::<Ranged>().get(item).map(|r| r.range);
+                        newrunstate = match range {
+                            Some(range) => RunState::TargettingInput(Targetting { range, item }),
+                            None => self.execute(Command::UseItem { slot, target: None }),
+                        };
+                    }
+                }
+            }
+            RunState::DropItemScreen => {
+                match display_inventory_selection(ctx, &mut self.ecs, "Drop Item") {
+                    gui::ItemMenuResult::Cancel => newrunstate = RunState::AwaitingInput,
+                    gui::ItemMenuResult::NoResponse => {}
+                    gui::ItemMenuResult::Selected { item: (slot, _) } => {
+                        newrunstate = self.execute(Command::DropItem { slot });
+                    }
+                }
+            }
+            RunState::RemoveItemScreen => {
+                match display_equipment_selection(ctx, &mut self.ecs, "Remove Item") {
+                    gui::ItemMenuResult::Cancel => newrunstate = RunState::AwaitingInput,
+                    gui::ItemMenuResult::NoResponse => {}
+                    gui::ItemMenuResult::Selected { item: (slot, _) } => {
+                        newrunstate = self.execute(Command::RemoveItem { slot });
+                    }
+                }
+            }
+            RunState::TargettingInput(Targetting { range, item }) => {
+                let radius = self.ecs.read_storage::<AreaOfEffect>().get(item).map_or(0, |a| a.radius);
+                let target = gui::ranged_target(&mut self.ecs, ctx, &camera, range, radius);
+                match target {
+                    gui::ItemMenuResult::Cancel => {newrunstate = RunState::AwaitingInput}
+                    gui::ItemMenuResult::NoResponse => {}
+                    gui::ItemMenuResult::Selected{item: i} => {
+                        newrunstate = match player::inventory_slot(&self.ecs, item) {
+                            Some(slot) => self.execute(Command::UseItem { slot, target: Some(i) }),
+                            None => RunState::AwaitingInput,
+                        };
+                    }
+                }
+            }
+            RunState::SaveGame => {
+                self.save_game();
+                newrunstate = RunState::AwaitingInput;
+            }
+            RunState::LoadGame => {
+                newrunstate = RunState::AwaitingInput;
+                if !saveload::does_save_exist() {
+                    self.ecs.write_resource::<GameLog>().say("There is no saved game.".into());
+                } else {
+                    if self.recorder.take().is_some() {
+                        warn!("Loaded a saved game, the replay stops recording here");
+                    }
+                    match saveload::load_game(&mut self.ecs) {
+                        Ok(()) => {
+                            newrunstate = *self.ecs.fetch::<RunState>();
+                            self.ecs.write_resource::<GameLog>().say("Game loaded.".into());
+                        }
+                        Err(e) => {
+                            // the world we had is untouched, back to the menu with it
+                            error!("Unable to load the game: {}", e);
+                            match saveload::set_aside_save() {
+                                Ok(path) => warn!("Moved the broken save to {}", path.display()),
+                                Err(e) => error!("Unable to move the broken save out of the way: {}", e),
+                            }
+                            newrunstate = RunState::MainMenu(gui::MainMenuSelection::NewGame);
+                        }
+                    }
+                }
+            }
+        }
+
+        {
+            let mut runwriter = self.ecs.write_resource::<RunState>();
+            *runwriter = newrunstate;
+        }
+
+        let seed = self.ecs.fetch::<util::Seed>().0;
+        ctx.print(1, 1, format!("Tetra Early Preview v{} - seed {}", VERSION, seed));
+    }
+}
+
+impl State {
+    /// A fully set up world with a fresh game in it, ready to run. The same
+    /// seed and the same inputs play out the same game.
+    pub fn new(seed: u64) -> State {
+        State::with_raws(seed, raws::RawMaster::embedded())
+    }
+
+    /// Same as `new`, but the monsters and items come from the given raws.
+    pub fn with_raws(seed: u64, raws: raws::RawMaster) -> State {
+        let mut ecs = World::new();
+        register_components(&mut ecs);
+
+        ecs.insert(SimpleMarkerAllocator::<saveload::SerializeMe>::new());
+        ecs.insert(raws);
+        seed_rngs(&mut ecs, seed);
+
+        new_game(&mut ecs);
+        State {
+            ecs,
+            recorder: None,
+            fixed_seed: None,
+        }
+    }
+
+    /// Throw the current game away, world and all, and set up a fresh one in
+    /// its place. The raws and the fixed seed carry over.
+    pub fn restart(&mut self) {
+        if self.recorder.take().is_some() {
+            warn!("The game was restarted, the replay stops recording here");
+        }
+        let raws = self
+            .ecs
+            .remove::<raws::RawMaster>()
+            .expect("The world has no raws to restart with");
+        let fixed_seed = self.fixed_seed;
+        *self = State::with_raws(fixed_seed.unwrap_or_else(util::random::random_seed), raws);
+        self.fixed_seed = fixed_seed;
+    }
+
+    /// Write the game out to the save file, says in the log how it went.
+    pub fn save_game(&mut self) -> bool {
+        // this is the state the game should resume in once its loaded
+        *self.ecs.write_resource::<RunState>() = RunState::AwaitingInput;
+        let (saved, msg) = match saveload::save_game(&mut self.ecs) {
+            Ok(()) => (true, "Game saved."),
+            Err(e) => {
+                error!("Unable to save the game: {}", e);
+                (false, "Unable to save the game.")
+            }
+        };
+        self.ecs.write_resource::<GameLog>().say(msg.to_string());
+        saved
+    }
+
+    /// One go of the world. In MonsterTurn thats a whole round, everybody
+    /// with the energy for it acts one action at a time, and whatever that
+    /// action did gets resolved before anybody acts again.
+    pub fn run_systems(&mut self) {
+        let mut initiative = systems::InitiativeSystem {};
+        let mut flow_maps = systems::FlowMapSystem {};
+        let mut status_effects = systems::StatusEffectSystem {};
+        let mut damage = systems::DamageSystem {};
+
+        initiative.run_now(&self.ecs);
+        flow_maps.run_now(&self.ecs);
+        // fast monsters keep going pass after pass until their energy runs out
+        while self.run_actions() {
+            let player = *self.ecs.fetch::<Entity>();
+            let player_alive = self.ecs.read_storage::<CombatStats>().get(player).is_some_and(|s| s.hp > 0);
+            if !player_alive {
+                break;
+            }
+            // the dead dont get another go
+            delete_the_dead(&mut self.ecs);
+        }
+        status_effects.run_now(&self.ecs);
+        damage.run_now(&self.ecs);
+
+        self.ecs.maintain();
+    }
+
+    /// Everybody who is ready takes one action, and it all gets resolved. True
+    /// if any of the monsters did something.
+    fn run_actions(&mut self) -> bool {
+        let mut vis = systems::VisibilitySystem {};
+        let mut melee = systems::MeleeCombatSystem {};
+        let mut damage = systems::DamageSystem {};
+        let mut ai = systems::MonsterAi { acted: false };
+        let mut mis = systems::MapIndexingSystem {};
+        let mut loot_system = systems::ItemCollectionSystem {};
+        let mut potions = systems::ItemUseSystem {};
+        let mut drop_items = systems::LootSystem {};
+        let mut remove_items = systems::ItemRemoveSystem {};
+        let mut doors = systems::DoorSystem {};
+
+        ai.run_now(&self.ecs);
+        doors.run_now(&self.ecs);
+        mis.run_now(&self.ecs);
+        // after the monsters, so their shots land in the same pass
+        potions.run_now(&self.ecs);
+        drop_items.run_now(&self.ecs);
+        remove_items.run_now(&self.ecs);
+        loot_system.run_now(&self.ecs);
+        vis.run_now(&self.ecs);
+        melee.run_now(&self.ecs);
+        damage.run_now(&self.ecs);
+
+        self.ecs.maintain();
+        ai.acted
+    }
+
+    /// Run the player command and hand back the state to continue in.
+    pub fn execute(&mut self, command: Command) -> RunState {
+        // worked out up front, the item might be gone once its used
+        let cost = player::action_cost(&self.ecs, command);
+        let next = player::execute(&mut self.ecs, command);
+        let turn = if next != RunState::AwaitingInput {
+            let player = *self.ecs.fetch::<Entity>();
+            if let Some(energy) = self.ecs.write_storage::<Energy>().get_mut(player) {
+                energy.energy -= cost;
+            }
+            let mut turn = self.ecs.write_resource::<Turn>();
+            turn.0 += 1;
+            turn.0
+        } else {
+            self.ecs.fetch::<Turn>().0
+        };
+        // turned down commands get recorded too, they can still say so in the log
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.command(turn, command);
+        }
+        if next == RunState::AwaitingInput {
+            // nothing plays out after it, so its as settled as its going to get
+            self.record_settled();
+        }
+        next
+    }
+
+    /// Writes the last command out to the replay, if we are recording.
+    fn record_settled(&mut self) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.settled(&self.ecs) {
+                error!("Unable to write to the replay, no longer recording: {}", e);
+                self.recorder = None;
+            }
+        }
+    }
+
+    /// Step through one of the states that doesnt need any input from the player,
+    /// the current state is read from and the next one written back to the world.
+    /// Rounds keep going by in MonsterTurn until the player has the energy to act.
+    pub fn advance(&mut self) -> RunState {
+        let current = *self.ecs.fetch::<RunState>();
+        match current {
+            RunState::PreRun | RunState::PlayerTurn | RunState::MonsterTurn => {}
+            RunState::NextLevel => {
+                // the new level gets its first look around in PreRun
+                next_level(&mut self.ecs);
+                *self.ecs.write_resource::<RunState>() = RunState::PreRun;
+                return RunState::PreRun;
+            }
+            other => return other,
+        };
+        self.run_systems();
+        // reap right away, so everything after this (drawing, the replay digest) sees the same world
+        let next = if delete_the_dead(&mut self.ecs) {
+            RunState::GameOver
+        } else if player_ready(&self.ecs) {
+            RunState::AwaitingInput
+        } else {
+            RunState::MonsterTurn
+        };
+        *self.ecs.write_resource::<RunState>() = next;
+
+        if next == RunState::AwaitingInput || next == RunState::GameOver {
+            self.record_settled();
+        }
+        next
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug, ConvertSaveload)]
+pub enum RunState {
+    AwaitingInput,
+    PreRun,
+    PlayerTurn,
+    MonsterTurn,
+    InventoryScreen,
+    DropItemScreen,
+    RemoveItemScreen,
+    TargettingInput(Targetting),
+    SaveGame,
+    LoadGame,
+    NextLevel,
+    MainMenu(gui::MainMenuSelection),
+    PauseMenu(gui::PauseMenuSelection),
+    GameOver,
+}
+
+// its own struct rather than fields on the variant, the ConvertSaveload derive
+// falls over on enum variants with more than one named field.
+#[derive(PartialEq, Copy, Clone, Debug, ConvertSaveload)]
+pub struct Targetting {
+    pub range: i32,
+    pub item: Entity,
+}
+
+/// Reaps everything that died this turn, true if the player was among them.
+/// The player entity itself is left alone, the game over screen wants it.
+pub fn delete_the_dead(ecs: &mut World) -> bool {
+    let mut dead: Vec<Entity> = Vec::new();
+    let mut player_died = false;
+    {
+        let combat_stats = ecs.read_storage::<CombatStats>();
+        let ranged_attacks = ecs.read_storage::<RangedAttack>();
+        let entities = ecs.entities();
+        let players = ecs.read_storage::<Player>();
+        let names = ecs.read_storage::<Name>();
+        let markers = ecs.read_storage::<SimpleMarker<saveload::SerializeMe>>();
+        let mut log = ecs.write_resource::<GameLog>();
+        for (entity, stats) in systems::in_creation_order(&markers, (&entities, &combat_stats).join(), |e| e.0) {
+            if stats.hp < 1 {
+                if players.get(entity).is_some() {
+                    log.say("You are dead".into());
+                    player_died = true;
+                } else {
+                    if let Some(victim_name) = names.get(entity) {
+                        log.say(format!("{} is dead", &victim_name.name));
+                    }
+                    dead.push(entity);
+                    // whatever it shot with goes with it
+                    if let Some(ranged) = ranged_attacks.get(entity) {
+                        dead.push(ranged.ability);
+                    }
+                }
+            }
+        }
+    }
+
+    for victim in dead {
+        ecs.delete_entity(victim).expect("Unable to delete");
+    }
+    player_died
+}
+
+fn display_inventory_selection(
+    ctx: &mut Rltk,
+    ecs: &mut World,
+    title: &str,
+) -> gui::ItemMenuResult<(usize, Entity)> {
+    let items = {
+        let player = *ecs.fetch::<Entity>();
+        player::inventory_contents(ecs, player)
+    };
+    display_item_selection(ctx, title, items)
+}
+
+fn display_equipment_selection(
+    ctx: &mut Rltk,
+    ecs: &mut World,
+    title: &str,
+) -> gui::ItemMenuResult<(usize, Entity)> {
+    let items = {
+        let player = *ecs.fetch::<Entity>();
+        player::equipped_contents(ecs, player)
+    };
+    display_item_selection(ctx, title, items)
+}
+
+fn display_item_selection(
+    ctx: &mut Rltk,
+    title: &str,
+    items: Vec<(Name, Entity)>,
+) -> gui::ItemMenuResult<(usize, Entity)> {
+    //TODO: i definitely should not hard code the location of this.
+    gui::draw_inventory_screen(
+        ctx,
+        15,
+        25,
+        title,
+        items
+            .iter()
+            .map(|(n, _)| &n.name)
+            .collect::<Vec<_>>()
+            .as_slice(),
+    );
+
+    match gui::inventory_menu_input(ctx, items.len()) {
+        gui::ItemMenuResult::Cancel => gui::ItemMenuResult::Cancel,
+        gui::ItemMenuResult::NoResponse => gui::ItemMenuResult::NoResponse,
+        gui::ItemMenuResult::Selected { item } => gui::ItemMenuResult::Selected {
+            item: (item, items[item].1),
+        },
+    }
+}
@@ -1,432 +1,100 @@
-use components::*;
-use log::*;
-use rltk::{GameState, Rltk, RltkBuilder, VirtualKeyCode};
+use rltk::RltkBuilder;
 use specs::prelude::*;
+use std::io::{self, BufRead};
+use tetra::components::*;
+use tetra::headless::Headless;
+use tetra::player::Command;
+use tetra::raws::RawMaster;
+use tetra::replay::{self, Recorder};
+use tetra::util::random::random_seed;
 
 extern crate env_logger;
-extern crate log;
-extern crate specs;
-
-mod components;
-mod draw;
-mod gui;
-mod systems;
-mod util;
-
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
 fn main() -> rltk::RltkError {
-    let mut context = RltkBuilder::simple80x50().with_title("Tetra").build()?;
-    context.with_post_scanlines(true);
-
-    let mut gs = State { ecs: World::new() };
-
     env_logger::init();
 
-    gs.ecs.register::<Position>();
-    gs.ecs.register::<draw::Renderable>();
-    gs.ecs.register::<Name>();
-    gs.ecs.register::<Viewshed>();
-    gs.ecs.register::<Monster>();
-    gs.ecs.register::<Player>();
-    gs.ecs.register::<CombatStats>();
-    gs.ecs.register::<BlocksTile>();
-    gs.ecs.register::<WantsToMelee>();
-    gs.ecs.register::<WantsToPickupItem>();
-    gs.ecs.register::<SufferDamage>();
-    gs.ecs.register::<ProvidesHealing>();
-    gs.ecs.register::<Item>();
-    gs.ecs.register::<InBackpack>();
-    gs.ecs.register::<Consumable>();
-    gs.ecs.register::<WantsToUseItem>();
-    gs.ecs.register::<WantsToDropItem>();
-    gs.ecs.register::<InflictsDamage>();
-    gs.ecs.register::<Ranged>();
-
-    {
-        let rng: util::RngResource = Box::new(rltk::RandomNumberGenerator::new());
-        gs.ecs.insert(rng);
-    }
-
-    let starting_room = {
-        const MAX_ROOMS: usize = 30;
-        const MIN_SIZE: i32 = 6;
-        const MAX_SIZE: i32 = 10;
-        const MAP_WIDTH: i32 = 80;
-        const MAP_HEIGHT: i32 = 43;
-
-        let map: map::TetraMap;
-
-        let res = {
-            let mut rng = gs.ecs.write_resource::<util::RngResource>();
-            map = map::new_map_rooms_and_corridors(
-                MAP_WIDTH,
-                MAP_HEIGHT,
-                std::iter::from_fn(|| {
-                    let w = rng.between(MIN_SIZE, MAX_SIZE);
-                    let h = rng.between(MIN_SIZE, MAX_SIZE);
-                    let x = rng.between(1, MAP_WIDTH - w) - 1;
-                    let y = rng.between(1, MAP_HEIGHT - h) - 1;
-                    Some(map::Room::new(x, y, w, h))
-                })
-                .take(MAX_ROOMS),
-            );
-            util::choose_element(rng.as_mut(), map.rooms.as_slice())
-        };
-
-        for res in res {
-            for (_i, room) in map.rooms.iter().enumerate() {
-                if room != res {
-                    spawner::spawn_room(&mut gs.ecs, room, spawner::SpawnerSettings::default());
-                }
-            }
-        }
-
-        let res = res.map(|x| x.center());
-        gs.ecs.insert(map);
-        gs.ecs.insert(GameLog {
-            entries: vec!["Welcome to tetra, young traveler !".to_string()],
-        });
-        res
+    let fixed_seed = match arg_value("--seed") {
+        Some(seed) => Some(seed.parse::<u64>()?),
+        None => None,
     };
+    let seed = fixed_seed.unwrap_or_else(random_seed);
 
-    for (x, y) in starting_room {
-        // insert the player location into the global store for some reason.
-        // gs.ecs.insert(pos.clone());
-
-        // Player entity yay
-        let player_entity = spawner::player(&mut gs.ecs, x, y);
-        gs.ecs.insert(player_entity);
-    }
-
-    gs.ecs.insert(RunState::PreRun);
-
-    rltk::main_loop(context, gs)
-}
-
-pub struct State {
-    pub ecs: World,
-}
-
-impl GameState for State {
-    fn tick(&mut self, ctx: &mut Rltk) {
-        ctx.cls();
-        delete_the_dead(&mut self.ecs);
-
-        // let map = self.ecs.fetch::<map::TetraMap>();
-        draw::draw_map(&self.ecs, ctx);
-
-        // Draw other renderable bs.
-        {
-            let positions = self.ecs.read_storage::<Position>();
-            let renderables = self.ecs.read_storage::<draw::Renderable>();
-            //TODO only draw when inside of the players viewshed.
-            let mut data: Vec<_> = (&positions, &renderables).join().collect();
-            data.sort_by_key(|(_, k)| -k.order);
-
-            for (pos, render) in data.iter() {
-                ctx.set(pos.x, pos.y, render.fg, render.bg, render.glyph);
-            }
-        }
-        gui::draw_ui(&self.ecs, ctx);
-
-        gui::draw_tooltips(&self.ecs, ctx);
-
-        let mut newrunstate = *self.ecs.fetch::<RunState>();
-
-        match newrunstate {
-            RunState::PreRun => {
-                self.run_systems();
-                newrunstate = RunState::AwaitingInput;
-            }
-            RunState::AwaitingInput => {
-                newrunstate = player_input(self, ctx);
-            }
-            RunState::PlayerTurn => {
-                self.run_systems();
-                newrunstate = RunState::MonsterTurn;
-            }
-            RunState::MonsterTurn => {
-                self.run_systems();
-                newrunstate = RunState::AwaitingInput;
-            }
-            RunState::InventoryScreen => {
-                match display_inventory_selection(ctx, &mut self.ecs, &"Use Item".into()) {
-                    Some((x, Some(item))) => {
-                        let mut intent = self.ecs.write_storage::<WantsToUseItem>();
-                        let ir = self.ecs.read_storage::<Ranged>();
-                        let is_item_ranged = ir.get(item);
-                        if let Some(is_item_ranged) = is_item_ranged {
-                            newrunstate = RunState::TargettingInput{range: is_item_ranged.range, item:  item};
-                        } else {
-                            intent
-                                .insert(*self.ecs.fetch::<Entity>(), WantsToUseItem { item, target: None })
-                                .expect("Unable to insert intent");
-                            newrunstate = x;
-                        }
-                    }
-                    Some((x, None)) => {
-                        newrunstate = x;
-                    }
-                    None => {}
-                }
-            }
-            RunState::DropItemScreen => {
-                match display_inventory_selection(ctx, &mut self.ecs, &"Drop Item".into()) {
-                    Some((x, Some(item))) => {
-                        let mut intent = self.ecs.write_storage::<WantsToDropItem>();
-                        intent
-                            .insert(*self.ecs.fetch::<Entity>(), WantsToDropItem { item })
-                            .expect("Unable to insert intent");
-                        newrunstate = x;
-                    }
-                    Some((x, None)) => {
-                        newrunstate = x;
-                    }
-                    None => {}
-                }
-            }
-            RunState::TargettingInput{range, item} =>{
-                let target = gui::ranged_target(&mut self.ecs, ctx, range);
-                match target {
-                    gui::ItemMenuResult::Cancel => {newrunstate = RunState::AwaitingInput}
-                    gui::ItemMenuResult::NoResponse => {}
-                    gui::ItemMenuResult::Selected{item: i} => {
-                        let mut intent = self.ecs.write_storage::<WantsToUseItem>();
-                        intent.insert(*self.ecs.fetch::<Entity>(), WantsToUseItem{item, target: Some(i)}).expect("cant insert intent");
-                        newrunstate = RunState::PlayerTurn;
-                    }
-                }
-            }
-        }
-
-        {
-            let mut runwriter = self.ecs.write_resource::<RunState>();
-            *runwriter = newrunstate;
-        }
-
-        ctx.print(1, 1, format!("Tetra Early Preview v{}", VERSION));
-    }
-}
-
-impl State {
-    fn run_systems(&mut self) {
-        let mut vis = systems::VisibilitySystem {};
-        let mut melee = systems::MeleeCombatSystem {};
-        let mut damage = systems::DamageSystem {};
-        let mut ai = systems::MonsterAi {};
-        let mut mis = systems::MapIndexingSystem {};
-        let mut loot_system = systems::ItemCollectionSystem {};
-        let mut potions = systems::ItemUseSystem {};
-        let mut drop_items = systems::LootSystem {};
-
-        potions.run_now(&self.ecs);
-        ai.run_now(&self.ecs);
-        mis.run_now(&self.ecs);
-        drop_items.run_now(&self.ecs);
-        loot_system.run_now(&self.ecs);
-        vis.run_now(&self.ecs);
-        melee.run_now(&self.ecs);
-        damage.run_now(&self.ecs);
+    let raws = match arg_value("--raws") {
+        Some(path) => RawMaster::from_file(path)?,
+        None => RawMaster::load()?,
+    };
 
-        self.ecs.maintain();
+    if let Some(path) = arg_value("--replay") {
+        let game = replay::replay(path, raws)?;
+        print_summary(&game);
+        println!("Replay verified.");
+        return Ok(());
     }
-}
 
-#[derive(PartialEq, Copy, Clone)]
-pub enum RunState {
-    AwaitingInput,
-    PreRun,
-    PlayerTurn,
-    MonsterTurn,
-    InventoryScreen,
-    DropItemScreen,
-    TargettingInput {
-        range: i32, item: Entity
+    let mut state = tetra::State::with_raws(seed, raws);
+    state.fixed_seed = fixed_seed;
+    if let Some(path) = arg_value("--record") {
+        state.recorder = Some(Recorder::create(path, seed)?);
     }
-}
 
-fn delete_the_dead(ecs: &mut World) {
-    let mut dead: Vec<Entity> = Vec::new();
-    {
-        let combat_stats = ecs.read_storage::<CombatStats>();
-        let entities = ecs.entities();
-        let players = ecs.read_storage::<Player>();
-        let names = ecs.read_storage::<Name>();
-        let mut log = ecs.write_resource::<GameLog>();
-        for (entity, stats) in (&entities, &combat_stats).join() {
-            if stats.hp < 1 {
-                if let Some(_) = players.get(entity) {
-                    log.say("You are dead".into());
-                } else {
-                    if let Some(victim_name) = names.get(entity) {
-                        log.say(format!("{} is dead", &victim_name.name));
-                    }
-                    dead.push(entity);
-                }
-            }
-        }
+    if std::env::args().any(|arg| arg == "--headless") {
+        return run_headless(state);
     }
 
-    for victim in dead {
-        ecs.delete_entity(victim).expect("Unable to delete");
-    }
-}
-
-fn player_input(gs: &mut State, ctx: &mut Rltk) -> RunState {
-    use VirtualKeyCode::*;
-    let mut res = RunState::AwaitingInput;
-
-    if let Some(key) = ctx.key {
-        res = RunState::PlayerTurn;
-        match key {
-            Up | K => try_move_player(0, -1, &mut gs.ecs),
-            Left | H => try_move_player(-1, 0, &mut gs.ecs),
-            Right | L => try_move_player(1, 0, &mut gs.ecs),
-            Down | J => try_move_player(0, 1, &mut gs.ecs),
-            Y => try_move_player(-1, -1, &mut gs.ecs),
-            U => try_move_player(1, -1, &mut gs.ecs),
-            N => try_move_player(1, 1, &mut gs.ecs),
-            B => try_move_player(-1, 1, &mut gs.ecs),
-            G => pickup_item(&mut gs.ecs),
-            I => res = RunState::InventoryScreen,
-            D => res = RunState::DropItemScreen,
-            _ => res = RunState::AwaitingInput,
-        }
-    }
+    *state.ecs.write_resource::<tetra::RunState>() =
+        tetra::RunState::MainMenu(tetra::gui::MainMenuSelection::NewGame);
+    let mut context = RltkBuilder::simple80x50().with_title("Tetra").build()?;
+    context.with_post_scanlines(true);
 
-    res
+    rltk::main_loop(context, state)
 }
 
-fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
-    let mut positions = ecs.write_storage::<Position>();
-    let mut players = ecs.write_storage::<Player>();
-    let mut viewsheds = ecs.write_storage::<Viewshed>();
-    let mut wants_to_melee = ecs.write_storage::<WantsToMelee>();
-    let combat_stats = ecs.read_storage::<CombatStats>();
-    let map = ecs.fetch::<map::TetraMap>();
-    let entities = ecs.entities();
-
-    fn clamp(m: i32, v: i32) -> i32 {
-        use std::cmp::{max, min};
-        min(m, max(0, v))
-    }
-
-    for (ent, _player, pos, viewshed) in
-        (&entities, &mut players, &mut positions, &mut viewsheds).join()
-    {
-        let new_x = clamp(map.width() - 1, pos.x + delta_x);
-        let new_y = clamp(map.height() - 1, pos.y + delta_y);
-
-        for potential_target in map.entities.get(pos.x + delta_x, pos.y + delta_y) {
-            if combat_stats.contains(*potential_target) {
-                debug!("From Hells heart i stab thee {:?}", potential_target);
-                wants_to_melee
-                    .insert(
-                        ent,
-                        WantsToMelee {
-                            target: *potential_target,
-                        },
-                    )
-                    .expect("Add target failed"); // FIXME i dont like this error handling.
-                return; // so we dont move after attacking, i guess thats a way to do it, i dont like it FIXME
-            }
-        }
-
-        if !map.is_blocked(new_x, new_y) {
-            pos.x = new_x;
-            pos.y = new_y;
-            viewshed.dirty = true;
-        }
-    }
+/// The argument right after `name` on the command line, eg `--seed 42`
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != name);
+    args.next();
+    args.next()
 }
 
-fn pickup_item(ecs: &mut World) {
-    let player = ecs.fetch::<Entity>();
-    let player = *player;
-    let entities = ecs.entities();
-    let items = ecs.read_storage::<Item>();
-    let positions = ecs.read_storage::<Position>();
-    let mut gamelog = ecs.fetch_mut::<GameLog>();
-
-    let mut target_item: Option<Entity> = None;
-    for (item_entity, _item, position) in (&entities, &items, &positions).join() {
-        let p_pos = positions.get(player).unwrap();
-        if position.x == p_pos.x && position.y == p_pos.y {
-            target_item = Some(item_entity);
+/// Plays a game from commands on stdin, one json encoded `Command` per line,
+/// and prints how it went once they run out.
+fn run_headless(state: tetra::State) -> rltk::RltkError {
+    let mut commands = Vec::new();
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            commands.push(serde_json::from_str::<Command>(&line)?);
         }
     }
 
-    match target_item {
-        None => gamelog
-            .entries
-            .push("There is nothing here to pickup.".to_string()),
-        Some(item) => {
-            let mut pickup = ecs.write_storage::<WantsToPickupItem>();
-            pickup
-                .insert(
-                    player,
-                    WantsToPickupItem {
-                        collected_by: player,
-                        item,
-                    },
-                )
-                .expect("Could not notify of item pickup");
-        }
+    let mut game = Headless::from_state(state);
+    game.run(commands);
+    print_summary(&game);
+    if game.player_dead() {
+        let path = tetra::morgue::write_morgue(&game.state.ecs)?;
+        println!("Morgue written to {}", path.display());
     }
+    Ok(())
 }
 
-fn display_inventory_selection(
-    ctx: &mut Rltk,
-    ecs: &mut World,
-    title: &String,
-) -> Option<(RunState, Option<Entity>)> {
-    let items = {
-        let player = *ecs.fetch::<Entity>();
-        inventory_contents(ecs, player)
-    };
-
-    //TODO: i definitely should not hard code the location of this.
-    gui::draw_inventory_screen(
-        ctx,
-        15,
-        25,
-        title,
-        items
-            .iter()
-            .map(|(n, _)| &n.name)
-            .collect::<Vec<_>>()
-            .as_slice(),
-    );
-
-    match gui::inventory_menu_input(ctx, items) {
-        gui::ItemMenuResult::Cancel => Some((RunState::AwaitingInput, None)),
-        gui::ItemMenuResult::NoResponse => None,
-        gui::ItemMenuResult::Selected{item} => Some((RunState::PlayerTurn, Some(item))),
+fn print_summary(game: &Headless) {
+    let ecs = &game.state.ecs;
+    for entry in ecs.fetch::<GameLog>().entries.iter() {
+        println!("{}", entry);
     }
-}
-
-
-fn inventory_contents<'a>(ecs: &'a mut World, player: Entity) -> Vec<(Name, Entity)> {
-    let names = ecs.read_storage::<Name>();
-    let backpack = ecs.read_storage::<InBackpack>();
-    let entities = ecs.entities();
-
-    let mut items = Vec::new();
-    for (entity, _pack, name) in (&entities, &backpack, &names)
-        .join()
-        .filter(|item| item.1.owner == player)
-    {
-        items.push((name.clone(), entity))
-    }
-    items
-}
 
-impl util::Rng for rltk::RandomNumberGenerator {
-    fn next_int(&mut self) -> i32 {
-        self.rand()
-    }
-    fn between(&mut self, k: i32, n: i32) -> i32 {
-        self.range(k, n)
+    let player = *ecs.fetch::<Entity>();
+    let stats = ecs.read_storage::<CombatStats>();
+    let positions = ecs.read_storage::<Position>();
+    if let (Some(stats), Some(pos)) = (stats.get(player), positions.get(player)) {
+        println!(
+            "seed: {}, turns: {}, hp: {} / {}, position: ({}, {})",
+            ecs.fetch::<tetra::util::Seed>().0,
+            ecs.fetch::<Turn>().0,
+            stats.hp,
+            stats.max_hp,
+            pos.x,
+            pos.y
+        );
     }
 }
@@ -0,0 +1,116 @@
+use super::{from_rooms, BuiltMap, MapBuilder};
+use crate::components::map::{self, Room, TetraMap, TileBuffer, TileType};
+use crate::util::{Rect, Rng};
+
+/// Keeps cutting the map in two until the pieces are small, puts a room in
+/// each piece and joins every pair of pieces that were cut apart.
+pub struct BspBuilder {
+    /// Pieces smaller than this in either direction dont get cut any further.
+    pub min_leaf: i32,
+    pub min_room: i32,
+}
+
+impl Default for BspBuilder {
+    fn default() -> Self {
+        BspBuilder {
+            min_leaf: 10,
+            min_room: 4,
+        }
+    }
+}
+
+enum Node {
+    Leaf(Room),
+    Split(Box<Node>, Box<Node>),
+}
+
+impl MapBuilder for BspBuilder {
+    fn name(&self) -> &'static str {
+        "binary space partition"
+    }
+
+    fn build(&mut self, rng: &mut dyn Rng, width: i32, height: i32, _depth: i32) -> Result<BuiltMap, String> {
+        let mut map = TetraMap::new(TileBuffer::new(width, height, TileType::Wall));
+        // leave the outermost tiles as wall
+        let tree = self.split(rng, Rect::new(0, 0, width - 2, height - 2));
+        self.carve(rng, &tree, &mut map);
+        map.gen_nav_buffer();
+
+        let start = map.rooms[rng.between(0, map.rooms.len() as i32) as usize];
+        from_rooms(map, start)
+    }
+}
+
+impl BspBuilder {
+    fn split(&self, rng: &mut dyn Rng, area: Rect) -> Node {
+        let w = area.x2 - area.x1;
+        let h = area.y2 - area.y1;
+        let can_split_x = w >= self.min_leaf * 2;
+        let can_split_y = h >= self.min_leaf * 2;
+
+        // cut across the long side, so we dont end up with thin slivers
+        let vertical = match (can_split_x, can_split_y) {
+            (false, false) => return Node::Leaf(area),
+            (true, false) => true,
+            (false, true) => false,
+            (true, true) => {
+                if w == h {
+                    rng.between(0, 2) == 0
+                } else {
+                    w > h
+                }
+            }
+        };
+
+        if vertical {
+            let cut = area.x1 + rng.between(self.min_leaf, w - self.min_leaf + 1);
+            Node::Split(
+                Box::new(self.split(rng, Rect { x2: cut, ..area })),
+                Box::new(self.split(rng, Rect { x1: cut, ..area })),
+            )
+        } else {
+            let cut = area.y1 + rng.between(self.min_leaf, h - self.min_leaf + 1);
+            Node::Split(
+                Box::new(self.split(rng, Rect { y2: cut, ..area })),
+                Box::new(self.split(rng, Rect { y1: cut, ..area })),
+            )
+        }
+    }
+
+    /// Puts the rooms in and tunnels between the two halves of every split,
+    /// hands back one of the rooms under this node to tunnel to.
+    fn carve(&self, rng: &mut dyn Rng, node: &Node, map: &mut TetraMap) -> Room {
+        match node {
+            Node::Leaf(leaf) => {
+                // one tile of wall either side, so rooms in neighbouring leaves never touch
+                let max_w = leaf.x2 - leaf.x1 - 2;
+                let max_h = leaf.y2 - leaf.y1 - 2;
+                let w = rng.between(self.min_room, max_w + 1);
+                let h = rng.between(self.min_room, max_h + 1);
+                let x = leaf.x1 + 1 + rng.between(0, max_w - w + 1);
+                let y = leaf.y1 + 1 + rng.between(0, max_h - h + 1);
+                let room = Room::new(x, y, w, h);
+                map.try_add_room(room);
+                room
+            }
+            Node::Split(left, right) => {
+                let a = self.carve(rng, left, map);
+                let b = self.carve(rng, right, map);
+                let (ax, ay) = a.center();
+                let (bx, by) = b.center();
+                if rng.between(0, 2) == 0 {
+                    map::apply_horizontal_tunnel(&mut map.buffer, ax, bx, ay);
+                    map::apply_vertical_tunnel(&mut map.buffer, ay, by, bx);
+                } else {
+                    map::apply_vertical_tunnel(&mut map.buffer, ay, by, ax);
+                    map::apply_horizontal_tunnel(&mut map.buffer, ax, bx, by);
+                }
+                if rng.between(0, 2) == 0 {
+                    a
+                } else {
+                    b
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use super::{distances_from, farthest_from, BuiltMap, MapBuilder};
+use crate::components::map::{TetraMap, TileBuffer, TileType};
+use crate::util::Rng;
+use std::collections::BTreeMap;
+
+/// Caves, grown out of noise by smoothing it over a few times.
+pub struct CellularAutomataBuilder {
+    /// Out of 100, how likely each tile starts off as a wall.
+    pub wall_chance: i32,
+    pub iterations: usize,
+    /// Caves have no rooms, so spawning happens in squares of this size instead.
+    pub region_size: i32,
+}
+
+impl Default for CellularAutomataBuilder {
+    fn default() -> Self {
+        CellularAutomataBuilder {
+            wall_chance: 55,
+            iterations: 15,
+            region_size: 10,
+        }
+    }
+}
+
+impl MapBuilder for CellularAutomataBuilder {
+    fn name(&self) -> &'static str {
+        "cellular automata"
+    }
+
+    fn build(&mut self, rng: &mut dyn Rng, width: i32, height: i32, _depth: i32) -> Result<BuiltMap, String> {
+        let mut buffer = TileBuffer::new(width, height, TileType::Wall);
+        for y in 1..height - 1 {
+            for x in 1..width - 1 {
+                if rng.between(0, 100) >= self.wall_chance {
+                    buffer.set(x, y, TileType::Floor);
+                }
+            }
+        }
+
+        for _ in 0..self.iterations {
+            buffer = smooth(&buffer);
+        }
+
+        // start from the floor closest to the middle of the map
+        let (cx, cy) = (width / 2, height / 2);
+        let start = floor_tiles(&buffer)
+            .min_by_key(|(x, y)| (x - cx).pow(2) + (y - cy).pow(2))
+            .ok_or("Cave came out without any floor")?;
+
+        let mut map = TetraMap::new(buffer);
+
+        // anything we cant walk to from the start gets filled in
+        let distances = distances_from(&map, start);
+        for (idx, tile) in map.buffer.data.iter_mut().enumerate() {
+            if *tile == TileType::Floor && distances[idx].is_none() {
+                *tile = TileType::Wall;
+            }
+        }
+        map.gen_nav_buffer();
+
+        // stairs down as far from the start as it gets
+        let exit = farthest_from(&map, start).ok_or("Cave is too small to put the stairs anywhere")?;
+
+        // chop the floor up into squares, leaving out the one the player starts in
+        let start_region = (start.0 / self.region_size, start.1 / self.region_size);
+        let mut regions: BTreeMap<(i32, i32), Vec<(i32, i32)>> = BTreeMap::new();
+        for (x, y) in floor_tiles(&map.buffer) {
+            let region = (x / self.region_size, y / self.region_size);
+            if region != start_region {
+                regions.entry(region).or_default().push((x, y));
+            }
+        }
+
+        Ok(BuiltMap {
+            map,
+            start,
+            exit,
+            spawn_regions: regions.into_values().collect(),
+        })
+    }
+}
+
+/// One round of the automaton, tiles crowded by walls (or with none at all
+/// around them) become walls, the rest open up.
+fn smooth(buffer: &TileBuffer) -> TileBuffer {
+    let mut next = buffer.clone();
+    for y in 1..buffer.height - 1 {
+        for x in 1..buffer.width - 1 {
+            let mut walls = 0;
+            for dy in -1..=1 {
+                for dx in -1..=1 {
+                    if (dx, dy) != (0, 0) && *buffer.get(x + dx, y + dy) == TileType::Wall {
+                        walls += 1;
+                    }
+                }
+            }
+            let tile = if walls > 4 || walls == 0 {
+                TileType::Wall
+            } else {
+                TileType::Floor
+            };
+            next.set(x, y, tile);
+        }
+    }
+    next
+}
+
+fn floor_tiles(buffer: &TileBuffer) -> impl Iterator<Item = (i32, i32)> + '_ {
+    buffer
+        .data
+        .iter()
+        .enumerate()
+        .filter(|(_, tile)| **tile == TileType::Floor)
+        .map(move |(idx, _)| (idx as i32 % buffer.width, idx as i32 / buffer.width))
+}
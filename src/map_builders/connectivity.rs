@@ -0,0 +1,179 @@
+use super::{distances_from, BuiltMap};
+use crate::components::map::{self, TetraMap, TileType};
+
+/// Pockets smaller than this get filled in, anything bigger gets a tunnel.
+const MIN_POCKET: usize = 8;
+
+/// What the connectivity pass found and did about it, it only goes in the log.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GenerationStats {
+    pub builder: String,
+    pub floor_tiles: usize,
+    pub reachable_tiles: usize,
+    pub pockets_connected: usize,
+    pub pockets_culled: usize,
+    pub tiles_culled: usize,
+    pub spawns_dropped: usize,
+}
+
+/// Makes sure everything walkable on the map can be walked to from the start,
+/// bigger pockets get tunnelled to, the little ones get filled in. Spawn
+/// regions get trimmed down to what can be reached. Errors if the stairs down
+/// still cant be walked to, or sit right where the player starts.
+pub fn ensure_connected(built: &mut BuiltMap) -> Result<GenerationStats, String> {
+    let mut stats = GenerationStats::default();
+    let map = &mut built.map;
+    map.gen_nav_buffer();
+
+    loop {
+        let reachable = reachable(map, built.start);
+        let pocket = match unreached_pocket(map, &reachable) {
+            Some(pocket) => pocket,
+            None => break,
+        };
+
+        if pocket.len() >= MIN_POCKET {
+            let from = closest_pair(map, &pocket, &reachable);
+            tunnel(map, from.0, from.1);
+            stats.pockets_connected += 1;
+        } else {
+            for idx in pocket.iter() {
+                map.buffer.data[*idx] = TileType::Wall;
+            }
+            stats.pockets_culled += 1;
+            stats.tiles_culled += pocket.len();
+        }
+        map.gen_nav_buffer();
+    }
+
+    let reachable = reachable(map, built.start);
+    stats.floor_tiles = map.buffer.data.iter().filter(|t| **t != TileType::Wall).count();
+    stats.reachable_tiles = reachable.iter().filter(|r| **r).count();
+
+    if built.exit == built.start {
+        return Err(format!("Exit at {:?} is on top of the start", built.exit));
+    }
+    if !reachable[map.xy_idx(built.exit.0, built.exit.1)] {
+        return Err(format!("Exit at {:?} cant be reached from {:?}", built.exit, built.start));
+    }
+
+    for region in built.spawn_regions.iter_mut() {
+        let before = region.len();
+        region.retain(|(x, y)| reachable[map.xy_idx(*x, *y)]);
+        stats.spawns_dropped += before - region.len();
+    }
+    built.spawn_regions.retain(|region| !region.is_empty());
+
+    Ok(stats)
+}
+
+/// Whether each tile can be walked to from `start`.
+pub fn reachable(map: &TetraMap, start: (i32, i32)) -> Vec<bool> {
+    distances_from(map, start).iter().map(|d| d.is_some()).collect()
+}
+
+/// The first walkable area (in tile order, so its the same every time) that
+/// cant be reached, as tile indices.
+fn unreached_pocket(map: &TetraMap, reachable: &[bool]) -> Option<Vec<usize>> {
+    let seed = (0..map.buffer.data.len())
+        .find(|idx| walkable(map, *idx) && !reachable[*idx])?;
+
+    let mut pocket = vec![seed];
+    let mut seen = vec![false; map.buffer.data.len()];
+    seen[seed] = true;
+    let mut next = 0;
+    while next < pocket.len() {
+        let (x, y) = map.xy(pocket[next]);
+        next += 1;
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= map.width() || ny >= map.height() {
+                    continue;
+                }
+                let idx = map.xy_idx(nx, ny);
+                if !seen[idx] && walkable(map, idx) {
+                    seen[idx] = true;
+                    pocket.push(idx);
+                }
+            }
+        }
+    }
+    Some(pocket)
+}
+
+fn walkable(map: &TetraMap, idx: usize) -> bool {
+    let (x, y) = map.xy(idx);
+    // the pathing never steps onto the outermost tiles, so they dont count
+    x > 0 && y > 0 && x < map.width() - 1 && y < map.height() - 1 && map.buffer.data[idx] != TileType::Wall
+}
+
+/// The tile in the pocket and the reachable tile that are closest together.
+fn closest_pair(map: &TetraMap, pocket: &[usize], reachable: &[bool]) -> ((i32, i32), (i32, i32)) {
+    let targets: Vec<(i32, i32)> = reachable
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| **r)
+        .map(|(idx, _)| map.xy(idx))
+        .collect();
+
+    let mut best = (i32::MAX, ((0, 0), (0, 0)));
+    for (px, py) in pocket.iter().map(|idx| map.xy(*idx)) {
+        for (tx, ty) in targets.iter() {
+            let distance = (px - tx).pow(2) + (py - ty).pow(2);
+            if distance < best.0 {
+                best = (distance, ((px, py), (*tx, *ty)));
+            }
+        }
+    }
+    best.1
+}
+
+fn tunnel(map: &mut TetraMap, (x1, y1): (i32, i32), (x2, y2): (i32, i32)) {
+    map::apply_horizontal_tunnel(&mut map.buffer, x1, x2, y1);
+    map::apply_vertical_tunnel(&mut map.buffer, y1, y2, x2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_builders::{all_builders, prefab::stamp_prefabs};
+    use crate::raws::RawMaster;
+    use crate::util::random::{seeded, Stream};
+    use crate::{MAP_HEIGHT, MAP_WIDTH};
+
+    #[test]
+    fn every_floor_tile_can_be_reached() {
+        let prefabs = RawMaster::embedded().prefabs;
+        let mut checked = 0;
+        for seed in 0..8 {
+            for mut builder in all_builders() {
+                let mut rng = seeded(seed, Stream::Map);
+                let depth = 1 + seed as i32 % 4;
+                let mut built = match builder.build(&mut rng, MAP_WIDTH, MAP_HEIGHT, depth) {
+                    Ok(built) => built,
+                    Err(_) => continue,
+                };
+                stamp_prefabs(&mut built.map, &prefabs, &mut rng, depth);
+                let stats = match ensure_connected(&mut built) {
+                    Ok(stats) => stats,
+                    Err(_) => continue,
+                };
+
+                let reachable = reachable(&built.map, built.start);
+                for (idx, reached) in reachable.iter().enumerate() {
+                    assert!(
+                        !walkable(&built.map, idx) || *reached,
+                        "{} with seed {} left {:?} cut off",
+                        builder.name(),
+                        seed,
+                        built.map.xy(idx)
+                    );
+                }
+                assert_eq!(stats.reachable_tiles, reachable.iter().filter(|r| **r).count());
+                checked += 1;
+            }
+        }
+        assert!(checked > 12, "Only {} of the levels came out", checked);
+    }
+}
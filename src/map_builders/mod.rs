@@ -0,0 +1,153 @@
+use crate::components::map::{Room, TetraMap, TileType};
+use crate::util::{Rng, RngResource};
+
+pub mod bsp;
+pub mod cellular_automata;
+pub mod connectivity;
+pub mod prefab;
+pub mod rooms_and_corridors;
+pub use bsp::BspBuilder;
+pub use cellular_automata::CellularAutomataBuilder;
+pub use connectivity::GenerationStats;
+pub use prefab::Prefab;
+pub use rooms_and_corridors::RoomsAndCorridorsBuilder;
+
+/// Everything a builder hands back, the map itself and where things should go on it.
+pub struct BuiltMap {
+    pub map: TetraMap,
+    /// Where the player arrives.
+    pub start: (i32, i32),
+    /// Where the stairs down go.
+    pub exit: (i32, i32),
+    /// Floor tiles to populate with monsters and items, one group per room,
+    /// cave pocket or whatever the builder deals in. The start area is left out.
+    pub spawn_regions: Vec<Vec<(i32, i32)>>,
+}
+
+pub trait MapBuilder {
+    /// Shows up in the logs, so we can tell which one built a broken level.
+    fn name(&self) -> &'static str;
+
+    /// Errors when the level came out unplayable, the caller rolls another one.
+    fn build(&mut self, rng: &mut dyn Rng, width: i32, height: i32, depth: i32) -> Result<BuiltMap, String>;
+}
+
+/// Every builder that can come up when one is picked at random.
+pub fn all_builders() -> Vec<Box<dyn MapBuilder>> {
+    vec![
+        Box::new(RoomsAndCorridorsBuilder::default()),
+        Box::new(CellularAutomataBuilder::default()),
+        Box::new(BspBuilder::default()),
+    ]
+}
+
+/// Start in the given room, stairs down in whichever room is furthest from
+/// it, and one spawn region for every other room.
+pub fn from_rooms(map: TetraMap, start: Room) -> Result<BuiltMap, String> {
+    let exit = *map
+        .rooms
+        .iter()
+        .filter(|room| **room != start)
+        .max_by_key(|room| {
+            let (x, y) = room.center();
+            let (sx, sy) = start.center();
+            (x - sx).pow(2) + (y - sy).pow(2)
+        })
+        .ok_or("Level was generated with only the one room, the stairs have nowhere to go")?;
+
+    let spawn_regions = map
+        .rooms
+        .iter()
+        .filter(|room| **room != start)
+        .map(room_tiles)
+        .collect();
+
+    Ok(BuiltMap {
+        start: start.center(),
+        exit: exit.center(),
+        spawn_regions,
+        map,
+    })
+}
+
+/// Closed doors wherever a corridor comes through the wall of a room, as long
+/// as it comes through straight, with wall on both sides of it.
+pub fn place_doors(map: &mut TetraMap) {
+    let mut doors = Vec::new();
+    for room in map.rooms.iter() {
+        let mut ring = Vec::new();
+        for x in room.x1 + 1..=room.x2 {
+            ring.push((x, room.y1));
+            ring.push((x, room.y2 + 1));
+        }
+        for y in room.y1 + 1..=room.y2 {
+            ring.push((room.x1, y));
+            ring.push((room.x2 + 1, y));
+        }
+
+        for (x, y) in ring {
+            if x < 1 || y < 1 || x >= map.width() - 1 || y >= map.height() - 1 {
+                continue;
+            }
+            let wall = |x, y| *map.buffer.get(x, y) == TileType::Wall;
+            let doorway = (wall(x - 1, y) && wall(x + 1, y)) || (wall(x, y - 1) && wall(x, y + 1));
+            if *map.buffer.get(x, y) == TileType::Floor && doorway {
+                doors.push((x, y));
+            }
+        }
+    }
+    for (x, y) in doors {
+        map.buffer.set(x, y, TileType::DoorClosed);
+    }
+}
+
+/// The floor inside the room, the walls round the edge left out.
+pub fn room_tiles(room: &Room) -> Vec<(i32, i32)> {
+    let mut tiles = Vec::new();
+    for y in room.y1 + 1..=room.y2 {
+        for x in room.x1 + 1..=room.x2 {
+            tiles.push((x, y));
+        }
+    }
+    tiles
+}
+
+/// How far every tile is from `start` walking over the nav buffer, None where
+/// it cant be reached at all.
+pub fn distances_from(map: &TetraMap, start: (i32, i32)) -> Vec<Option<f32>> {
+    let dijkstra = rltk::DijkstraMap::new(
+        map.width(),
+        map.height(),
+        &[map.xy_idx(start.0, start.1)],
+        map,
+        f32::MAX,
+    );
+    dijkstra
+        .map
+        .iter()
+        .map(|d| if *d < f32::MAX { Some(*d) } else { None })
+        .collect()
+}
+
+/// The reachable tile furthest from `start`, never `start` itself, None if
+/// there is nowhere else to walk to.
+pub fn farthest_from(map: &TetraMap, start: (i32, i32)) -> Option<(i32, i32)> {
+    distances_from(map, start)
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, d)| d.map(|d| (idx, d)))
+        .filter(|(idx, _)| map.xy(*idx) != start)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(idx, _)| map.xy(idx))
+}
+
+/// The builder for the given depth, the first level is always rooms and
+/// corridors, after that its whatever the dice say.
+pub fn builder_for_depth(depth: i32, rng: &mut RngResource) -> Box<dyn MapBuilder> {
+    if depth <= 1 {
+        return Box::new(RoomsAndCorridorsBuilder::default());
+    }
+    let mut builders = all_builders();
+    let idx = rng.between(0, builders.len() as i32) as usize;
+    builders.swap_remove(idx)
+}
@@ -0,0 +1,309 @@
+use crate::components::map::{self, TetraMap, TileType};
+use crate::util::Rng;
+use std::collections::HashMap;
+
+/// A hand drawn room, read from a text file like this:
+///
+/// ```text
+/// name: Orc Den
+/// min_depth: 2
+/// chance: 30
+/// o = Orc
+/// ! = Health Potion
+/// ---
+/// #######
+/// #o...!#
+/// ....o.#
+/// #######
+/// ```
+///
+/// Above the `---` are the settings and the legend, below it the room itself.
+/// `#` (or a space) is wall, `.` is floor, `+` a closed door, and every character in the legend
+/// is floor with whatever the raws call that name spawned on it. The room
+/// needs at least one floor tile on its edge, thats where it gets joined up
+/// with the rest of the level.
+#[derive(Clone, Debug)]
+pub struct Prefab {
+    pub name: String,
+    pub min_depth: i32,
+    pub max_depth: Option<i32>,
+    /// Out of 100, how likely it is to show up on a level it can go on.
+    pub chance: i32,
+    pub legend: HashMap<char, String>,
+    pub width: i32,
+    pub height: i32,
+    rows: Vec<Vec<char>>,
+}
+
+impl Prefab {
+    pub fn parse(text: &str) -> Result<Prefab, String> {
+        let mut lines = text.lines();
+        let mut name = None;
+        let mut min_depth = 1;
+        let mut max_depth = None;
+        let mut chance = 25;
+        let mut legend = HashMap::new();
+
+        for line in lines.by_ref() {
+            let line = line.trim();
+            if line == "---" {
+                break;
+            }
+            if line.is_empty() {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once(" = ") {
+                let mut chars = key.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if !matches!(c, '#' | '.' | ' ' | '+') => {
+                        legend.insert(c, value.trim().to_string());
+                    }
+                    _ => return Err(format!("\"{}\" is not a single character legend entry", key)),
+                }
+                continue;
+            }
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| format!("don't know what to do with \"{}\"", line))?;
+            let value = value.trim();
+            let number = || {
+                value
+                    .parse::<i32>()
+                    .map_err(|_| format!("{} has to be a number, not \"{}\"", key, value))
+            };
+            match key.trim() {
+                "name" => name = Some(value.to_string()),
+                "min_depth" => min_depth = number()?,
+                "max_depth" => max_depth = Some(number()?),
+                "chance" => chance = number()?,
+                other => return Err(format!("unknown setting \"{}\"", other)),
+            }
+        }
+
+        let mut rows: Vec<Vec<char>> = lines
+            .map(|line| line.trim_end().chars().collect())
+            .collect();
+        while rows.last().is_some_and(|row| row.is_empty()) {
+            rows.pop();
+        }
+        let width = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        for row in rows.iter_mut() {
+            row.resize(width, ' ');
+        }
+
+        let prefab = Prefab {
+            name: name.ok_or("it needs a name")?,
+            min_depth,
+            max_depth,
+            chance,
+            legend,
+            width: width as i32,
+            height: rows.len() as i32,
+            rows,
+        };
+
+        for c in prefab.rows.iter().flatten() {
+            if !matches!(c, '#' | '.' | ' ' | '+') && !prefab.legend.contains_key(c) {
+                return Err(format!("'{}' is not in the legend", c));
+            }
+        }
+        if prefab.openings().is_empty() {
+            return Err("it needs a floor tile on its edge to connect through".into());
+        }
+        Ok(prefab)
+    }
+
+    pub fn spawns_at(&self, depth: i32) -> bool {
+        depth >= self.min_depth && self.max_depth.is_none_or(|max| depth <= max)
+    }
+
+    fn tile(&self, x: i32, y: i32) -> TileType {
+        match self.rows[y as usize][x as usize] {
+            '#' | ' ' => TileType::Wall,
+            '+' => TileType::DoorClosed,
+            _ => TileType::Floor,
+        }
+    }
+
+    /// Floor tiles on the edge of the room, with the direction that leads out of it.
+    fn openings(&self) -> Vec<((i32, i32), (i32, i32))> {
+        let mut openings = Vec::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                if self.tile(x, y) == TileType::Wall {
+                    continue;
+                }
+                if x == 0 {
+                    openings.push(((x, y), (-1, 0)));
+                } else if x == self.width - 1 {
+                    openings.push(((x, y), (1, 0)));
+                } else if y == 0 {
+                    openings.push(((x, y), (0, -1)));
+                } else if y == self.height - 1 {
+                    openings.push(((x, y), (0, 1)));
+                }
+            }
+        }
+        openings
+    }
+}
+
+// how many spots we look at before giving up on fitting a prefab in
+const PLACEMENT_TRIES: usize = 50;
+
+/// Stamps whichever of the prefabs make their roll into solid rock on the map,
+/// tunnels each one through to the nearest floor. Hands back what has to be
+/// spawned where.
+pub fn stamp_prefabs(
+    map: &mut TetraMap,
+    prefabs: &[Prefab],
+    rng: &mut dyn Rng,
+    depth: i32,
+) -> Vec<((i32, i32), String)> {
+    let mut spawns = Vec::new();
+    for prefab in prefabs.iter().filter(|p| p.spawns_at(depth)) {
+        if rng.between(0, 100) >= prefab.chance {
+            continue;
+        }
+        match place(map, prefab, rng) {
+            Some(placed) => {
+                log::info!("Stamped {} at {:?}", prefab.name, placed);
+                spawns.extend(stamp(map, prefab, placed));
+            }
+            None => log::info!("No room for {} on this level", prefab.name),
+        }
+    }
+    map.gen_nav_buffer();
+    spawns
+}
+
+/// Somewhere the prefab fits entirely inside solid rock, with an opening
+/// that faces some floor it can be tunnelled to.
+fn place(map: &TetraMap, prefab: &Prefab, rng: &mut dyn Rng) -> Option<(i32, i32)> {
+    if prefab.width + 2 >= map.width() || prefab.height + 2 >= map.height() {
+        return None;
+    }
+    for _ in 0..PLACEMENT_TRIES {
+        let x = rng.between(1, map.width() - prefab.width);
+        let y = rng.between(1, map.height() - prefab.height);
+        if is_solid(map, x - 1, y - 1, prefab.width + 2, prefab.height + 2)
+            && connection(map, prefab, (x, y)).is_some()
+        {
+            return Some((x, y));
+        }
+    }
+    None
+}
+
+fn is_solid(map: &TetraMap, x: i32, y: i32, w: i32, h: i32) -> bool {
+    (y..y + h).all(|ty| (x..x + w).all(|tx| *map.buffer.get(tx, ty) == TileType::Wall))
+}
+
+/// Where a tunnel out of a prefab starts and where it ends up.
+type Connection = ((i32, i32), (i32, i32));
+
+/// The opening and the floor tile closest to it on the side the opening faces.
+fn connection(map: &TetraMap, prefab: &Prefab, (px, py): (i32, i32)) -> Option<Connection> {
+    let mut best: Option<(i32, Connection)> = None;
+    for ((ox, oy), (dx, dy)) in prefab.openings() {
+        let (ox, oy) = (px + ox, py + oy);
+        for (idx, tile) in map.buffer.data.iter().enumerate() {
+            if *tile == TileType::Wall {
+                continue;
+            }
+            let (fx, fy) = map.xy(idx);
+            // only floor out past the opening, so the tunnel doesnt cut back through the room
+            let outside = (dx < 0 && fx < px)
+                || (dx > 0 && fx >= px + prefab.width)
+                || (dy < 0 && fy < py)
+                || (dy > 0 && fy >= py + prefab.height);
+            if !outside {
+                continue;
+            }
+            let distance = (fx - ox).pow(2) + (fy - oy).pow(2);
+            if best.is_none_or(|(d, _)| distance < d) {
+                best = Some((distance, ((ox, oy), (fx, fy))));
+            }
+        }
+    }
+    best.map(|(_, connection)| connection)
+}
+
+fn stamp(map: &mut TetraMap, prefab: &Prefab, (px, py): (i32, i32)) -> Vec<((i32, i32), String)> {
+    let ((ox, oy), (fx, fy)) =
+        connection(map, prefab, (px, py)).expect("Prefab was placed without a connection");
+
+    let mut spawns = Vec::new();
+    for y in 0..prefab.height {
+        for x in 0..prefab.width {
+            map.buffer.set(px + x, py + y, prefab.tile(x, y));
+            if let Some(name) = prefab.legend.get(&prefab.rows[y as usize][x as usize]) {
+                spawns.push(((px + x, py + y), name.clone()));
+            }
+        }
+    }
+
+    // out of the opening first, so the tunnel leaves the room the way the opening faces
+    if ox == px || ox == px + prefab.width - 1 {
+        map::apply_horizontal_tunnel(&mut map.buffer, ox, fx, oy);
+        map::apply_vertical_tunnel(&mut map.buffer, oy, fy, fx);
+    } else {
+        map::apply_vertical_tunnel(&mut map.buffer, oy, fy, ox);
+        map::apply_horizontal_tunnel(&mut map.buffer, ox, fx, fy);
+    }
+    spawns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::map::TileBuffer;
+    use crate::util::random::{seeded, Stream};
+
+    const DEN: &str = "name: Den\nchance: 100\no = Orc\n---\n#.###\n#o..\n###\n";
+
+    #[test]
+    fn ragged_rows_are_walled_off() {
+        let prefab = Prefab::parse(DEN).unwrap();
+        assert_eq!((prefab.width, prefab.height), (5, 3));
+        // the short rows get padded out with wall
+        assert_eq!(prefab.tile(4, 1), TileType::Wall);
+        assert_eq!(prefab.tile(3, 2), TileType::Wall);
+        assert_eq!(prefab.tile(3, 1), TileType::Floor);
+        assert_eq!(prefab.legend.get(&'o').map(String::as_str), Some("Orc"));
+    }
+
+    #[test]
+    fn rejects_unknown_glyphs() {
+        let err = Prefab::parse("name: Den\n---\n#.#\n#x#\n").unwrap_err();
+        assert!(err.contains("'x'"), "{}", err);
+        assert!(Prefab::parse("name: Den\nxy = Orc\n---\n#.#\n").is_err());
+    }
+
+    #[test]
+    fn rejects_rooms_with_no_way_in() {
+        assert!(Prefab::parse("name: Den\n---\n###\n#.#\n###\n").is_err());
+        assert!(Prefab::parse("---\n#.#\n").is_err());
+    }
+
+    #[test]
+    fn stamps_stay_inside_the_border() {
+        let prefab = Prefab::parse(DEN).unwrap();
+        for seed in 0..50 {
+            let (width, height) = (20, 12);
+            let mut buffer = TileBuffer::new(width, height, TileType::Wall);
+            buffer.set(width / 2, height / 2, TileType::Floor);
+            let mut map = TetraMap::new(buffer);
+            let mut rng = seeded(seed, Stream::Map);
+
+            let spawns = stamp_prefabs(&mut map, std::slice::from_ref(&prefab), &mut rng, 1);
+            assert_eq!(spawns.len(), 1, "seed {} didnt place the den", seed);
+            for (idx, tile) in map.buffer.data.iter().enumerate() {
+                let (x, y) = map.xy(idx);
+                if x == 0 || y == 0 || x == width - 1 || y == height - 1 {
+                    assert_eq!(*tile, TileType::Wall, "seed {} dug into the border at {:?}", seed, (x, y));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use super::{from_rooms, BuiltMap, MapBuilder};
+use crate::components::map::{self, Room};
+use crate::util::{choose_element, Rng};
+
+/// Rectangular rooms dropped wherever they fit, each one tunnelled to the one before it.
+pub struct RoomsAndCorridorsBuilder {
+    pub max_rooms: usize,
+    pub min_size: i32,
+    pub max_size: i32,
+}
+
+impl Default for RoomsAndCorridorsBuilder {
+    fn default() -> Self {
+        RoomsAndCorridorsBuilder {
+            max_rooms: 30,
+            min_size: 6,
+            max_size: 10,
+        }
+    }
+}
+
+impl MapBuilder for RoomsAndCorridorsBuilder {
+    fn name(&self) -> &'static str {
+        "rooms and corridors"
+    }
+
+    fn build(&mut self, rng: &mut dyn Rng, width: i32, height: i32, _depth: i32) -> Result<BuiltMap, String> {
+        let (min_size, max_size) = (self.min_size, self.max_size);
+        let map = map::new_map_rooms_and_corridors(
+            width,
+            height,
+            std::iter::from_fn(|| {
+                let w = rng.between(min_size, max_size);
+                let h = rng.between(min_size, max_size);
+                let x = rng.between(1, width - w) - 1;
+                let y = rng.between(1, height - h) - 1;
+                Some(Room::new(x, y, w, h))
+            })
+            .take(self.max_rooms),
+        );
+
+        let start = *choose_element(rng, map.rooms.as_slice())
+            .ok_or("Level was generated without any rooms")?;
+
+        from_rooms(map, start)
+    }
+}
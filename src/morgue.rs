@@ -0,0 +1,136 @@
+use crate::components::{map::TileType, *};
+use crate::player;
+use specs::prelude::*;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// How much of the log makes it into the morgue.
+const LOG_ENTRIES: usize = 20;
+
+/// Dump a plain text account of how the run went next to the save file, hands
+/// back where it ended up.
+pub fn write_morgue(ecs: &World) -> io::Result<PathBuf> {
+    let path = PathBuf::from(format!(
+        "./morgue-{}-{}.txt",
+        ecs.fetch::<crate::util::Seed>().0,
+        ecs.fetch::<Turn>().0
+    ));
+    fs::write(&path, morgue(ecs))?;
+    Ok(path)
+}
+
+/// The text of the morgue file for the run as it stands.
+pub fn morgue(ecs: &World) -> String {
+    let player = *ecs.fetch::<Entity>();
+    let mut out = String::new();
+
+    // writing into a String doesnt fail, so the results get dropped throughout
+    let _ = writeln!(
+        out,
+        "Tetra v{} - seed {}",
+        env!("CARGO_PKG_VERSION"),
+        ecs.fetch::<crate::util::Seed>().0
+    );
+    let depth = ecs.fetch::<Depth>().0;
+    let turns = ecs.fetch::<Turn>().0;
+    match &ecs.fetch::<KilledBy>().0 {
+        Some(killer) => {
+            let _ = writeln!(out, "Killed by {} on depth {} after {} turns.", killer, depth, turns);
+        }
+        None => {
+            let _ = writeln!(out, "Made it to depth {} in {} turns.", depth, turns);
+        }
+    }
+    out.push('\n');
+
+    if let Some(stats) = ecs.read_storage::<CombatStats>().get(player) {
+        let _ = writeln!(
+            out,
+            "Hp: {} / {}  Power: {}  Defense: {}",
+            stats.hp, stats.max_hp, stats.power, stats.defense
+        );
+        out.push('\n');
+    }
+
+    out.push_str("Equipped:\n");
+    write_items(&mut out, &player::equipped_contents(ecs, player));
+    out.push_str("Inventory:\n");
+    write_items(&mut out, &player::inventory_contents(ecs, player));
+    out.push('\n');
+
+    out.push_str("Last messages:\n");
+    let log = ecs.fetch::<GameLog>();
+    let skip = log.entries.len().saturating_sub(LOG_ENTRIES);
+    for entry in log.entries.iter().skip(skip) {
+        let _ = writeln!(out, "  {}", entry);
+    }
+    out.push('\n');
+
+    let _ = writeln!(out, "Depth {} as it was known:", depth);
+    out.push_str(&revealed_map(ecs, player));
+    out
+}
+
+fn write_items(out: &mut String, items: &[(Name, Entity)]) {
+    if items.is_empty() {
+        out.push_str("  nothing\n");
+    }
+    for (i, (name, _)) in items.iter().enumerate() {
+        let _ = writeln!(out, "  {}) {}", (b'a' + i as u8) as char, name.name);
+    }
+}
+
+/// The map as far as the player has seen it, with an @ where they fell.
+fn revealed_map(ecs: &World, player: Entity) -> String {
+    let map = ecs.fetch::<map::TetraMap>();
+    let players = ecs.read_storage::<Player>();
+    let revealed = match players.get(player) {
+        Some(p) => &p.revealed_tiles,
+        None => return String::new(),
+    };
+    let at = ecs
+        .read_storage::<Position>()
+        .get(player)
+        .map(|pos| (pos.x, pos.y));
+
+    let mut lines = Vec::new();
+    for y in 0..map.height() {
+        let mut line: String = (0..map.width())
+            .map(|x| {
+                let idx = map.xy_idx(x, y);
+                if at == Some((x, y)) {
+                    '@'
+                } else if !revealed.contains(&idx) {
+                    ' '
+                } else {
+                    match map.buffer.data[idx] {
+                        TileType::Floor => '.',
+                        TileType::Wall => '#',
+                        TileType::DownStairs => '>',
+                        TileType::UpStairs => '<',
+                        TileType::DoorClosed => '+',
+                        TileType::DoorOpen => '/',
+                    }
+                }
+            })
+            .collect();
+        line.truncate(line.trim_end().len());
+        lines.push(line);
+    }
+
+    // whatever nobody has seen around the edges doesnt need to be in there at all
+    let first = lines.iter().position(|l| !l.is_empty()).unwrap_or(lines.len());
+    let last = lines.iter().rposition(|l| !l.is_empty()).map_or(first, |i| i + 1);
+    let indent = lines[first..last]
+        .iter()
+        .filter(|l| !l.is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    lines[first..last]
+        .iter()
+        .map(|l| format!("{}\n", l.get(indent..).unwrap_or("")))
+        .collect()
+}
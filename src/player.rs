@@ -0,0 +1,433 @@
+use crate::components::*;
+use crate::raws::DEFAULT_FACTION;
+use crate::util::{Rng, RngResource};
+use crate::{RunState, State};
+use log::*;
+use rltk::{Rltk, VirtualKeyCode};
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+
+/// Everything the player can decide to do on their turn, whether it came from
+/// the keyboard, a menu or a script.
+#[derive(Serialize, Deserialize, PartialEq, Copy, Clone, Debug)]
+pub enum Command {
+    Move { dx: i32, dy: i32 },
+    Wait,
+    PickUp,
+    CloseDoor,
+    Descend,
+    /// slot is the index into the players inventory, same order as the inventory screen
+    UseItem {
+        slot: usize,
+        target: Option<(i32, i32)>,
+    },
+    DropItem { slot: usize },
+    /// slot is the index into what the player is wearing, same order as the remove screen
+    RemoveItem { slot: usize },
+}
+
+pub fn player_input(gs: &mut State, ctx: &mut Rltk) -> RunState {
+    use VirtualKeyCode::*;
+
+    let command = match ctx.key {
+        None => return RunState::AwaitingInput,
+        Some(key) => match key {
+            Up | K => Command::Move { dx: 0, dy: -1 },
+            Left | H => Command::Move { dx: -1, dy: 0 },
+            Right | L => Command::Move { dx: 1, dy: 0 },
+            Down | J => Command::Move { dx: 0, dy: 1 },
+            Y => Command::Move { dx: -1, dy: -1 },
+            U => Command::Move { dx: 1, dy: -1 },
+            N => Command::Move { dx: 1, dy: 1 },
+            B => Command::Move { dx: -1, dy: 1 },
+            G => Command::PickUp,
+            C => Command::CloseDoor,
+            Period => Command::Descend,
+            I => return RunState::InventoryScreen,
+            D => return RunState::DropItemScreen,
+            R => return RunState::RemoveItemScreen,
+            F5 => return RunState::SaveGame,
+            F9 => return RunState::LoadGame,
+            Escape => return RunState::PauseMenu(crate::gui::PauseMenuSelection::Resume),
+            _ => return RunState::AwaitingInput,
+        },
+    };
+
+    gs.execute(command)
+}
+
+/// Apply the command for the player, returns the state the game should move on to.
+pub fn execute(ecs: &mut World, command: Command) -> RunState {
+    if crate::player_has(ecs, StatusKind::Sleep) {
+        // whatever they wanted to do, the turn goes by anyway
+        ecs.write_resource::<GameLog>().say("You are fast asleep.".into());
+        return RunState::PlayerTurn;
+    }
+
+    match command {
+        Command::Move { dx, dy } => try_move_player(dx, dy, ecs),
+        Command::Wait => {}
+        Command::PickUp => pickup_item(ecs),
+        Command::CloseDoor => {
+            if !close_doors(ecs) {
+                ecs.write_resource::<GameLog>()
+                    .say("There is no open door next to you.".into());
+                return RunState::AwaitingInput;
+            }
+        }
+        Command::Descend => {
+            if player_tile(ecs) == map::TileType::DownStairs {
+                return RunState::NextLevel;
+            }
+            ecs.write_resource::<GameLog>()
+                .say("There is no way down from here.".into());
+            return RunState::AwaitingInput;
+        }
+        Command::UseItem { slot, target } => {
+            let player = *ecs.fetch::<Entity>();
+            match inventory_item(ecs, player, slot) {
+                Some(item) => {
+                    ecs.write_storage::<WantsToUseItem>()
+                        .insert(player, WantsToUseItem { item, target })
+                        .expect("Unable to insert intent");
+                }
+                None => return RunState::AwaitingInput,
+            }
+        }
+        Command::RemoveItem { slot } => {
+            let player = *ecs.fetch::<Entity>();
+            match equipped_contents(ecs, player).get(slot) {
+                Some((_, item)) => {
+                    ecs.write_storage::<WantsToRemoveItem>()
+                        .insert(player, WantsToRemoveItem { item: *item })
+                        .expect("Unable to insert intent");
+                }
+                None => return RunState::AwaitingInput,
+            }
+        }
+        Command::DropItem { slot } => {
+            let player = *ecs.fetch::<Entity>();
+            match inventory_item(ecs, player, slot) {
+                Some(item) => {
+                    ecs.write_storage::<WantsToDropItem>()
+                        .insert(player, WantsToDropItem { item })
+                        .expect("Unable to insert intent");
+                }
+                None => return RunState::AwaitingInput,
+            }
+        }
+    }
+    RunState::PlayerTurn
+}
+
+/// Energy the command takes out of the player, see ACTION_COST. Drinking is
+/// quick, strapping on gear takes a while.
+pub fn action_cost(ecs: &World, command: Command) -> i32 {
+    if crate::player_has(ecs, StatusKind::Sleep) {
+        return ACTION_COST;
+    }
+    match command {
+        Command::UseItem { slot, .. } => {
+            let player = *ecs.fetch::<Entity>();
+            match inventory_item(ecs, player, slot) {
+                Some(item) if ecs.read_storage::<Equippable>().contains(item) => ACTION_COST * 2,
+                Some(item) if ecs.read_storage::<Ranged>().contains(item) => ACTION_COST,
+                Some(_) => ACTION_COST / 2,
+                None => ACTION_COST,
+            }
+        }
+        Command::DropItem { .. } => ACTION_COST / 2,
+        _ => ACTION_COST,
+    }
+}
+
+fn try_move_player(delta_x: i32, delta_y: i32, ecs: &mut World) {
+    let (delta_x, delta_y) = if crate::player_has(ecs, StatusKind::Confusion) {
+        let mut rng = ecs.write_resource::<RngResource>();
+        (rng.between(-1, 2), rng.between(-1, 2))
+    } else {
+        (delta_x, delta_y)
+    };
+    if delta_x == 0 && delta_y == 0 {
+        return;
+    }
+
+    let mut positions = ecs.write_storage::<Position>();
+    let mut players = ecs.write_storage::<Player>();
+    let mut viewsheds = ecs.write_storage::<Viewshed>();
+    let mut wants_to_melee = ecs.write_storage::<WantsToMelee>();
+    let combat_stats = ecs.read_storage::<CombatStats>();
+    let factions = ecs.read_storage::<Faction>();
+    let statuses = ecs.read_storage::<StatusEffects>();
+    let names = ecs.read_storage::<Name>();
+    let mut map = ecs.fetch_mut::<map::TetraMap>();
+    let entities = ecs.entities();
+    let mut opened_door = false;
+    let mut swapped = None;
+
+    fn clamp(m: i32, v: i32) -> i32 {
+        use std::cmp::{max, min};
+        min(m, max(0, v))
+    }
+
+    for (ent, _player, pos, viewshed) in
+        (&entities, &mut players, &mut positions, &mut viewsheds).join()
+    {
+        let new_x = clamp(map.width() - 1, pos.x + delta_x);
+        let new_y = clamp(map.height() - 1, pos.y + delta_y);
+        let mine = factions.get(ent).map_or(PLAYER_FACTION, |f| f.allegiance(statuses.get(ent)));
+
+        for potential_target in map.entities.get(pos.x + delta_x, pos.y + delta_y) {
+            if !combat_stats.contains(*potential_target) {
+                continue;
+            }
+            let theirs = factions
+                .get(*potential_target)
+                .map_or(DEFAULT_FACTION, |f| f.allegiance(statuses.get(*potential_target)));
+            if theirs == mine {
+                // allies get shuffled past, anybody else gets hit, even if they were minding their own business
+                swapped = Some((*potential_target, (pos.x, pos.y)));
+                pos.x = new_x;
+                pos.y = new_y;
+                viewshed.dirty = true;
+                break;
+            }
+            debug!("From Hells heart i stab thee {:?}", potential_target);
+            wants_to_melee
+                .insert(
+                    ent,
+                    WantsToMelee {
+                        target: *potential_target,
+                    },
+                )
+                .expect("Add target failed"); // FIXME i dont like this error handling.
+            return; // so we dont move after attacking, i guess thats a way to do it, i dont like it FIXME
+        }
+        if swapped.is_some() {
+            continue;
+        }
+
+        // bumping into a closed door opens it, that takes the move
+        if map.open_door(new_x, new_y) {
+            opened_door = true;
+            continue;
+        }
+
+        if !map.is_blocked(new_x, new_y) {
+            pos.x = new_x;
+            pos.y = new_y;
+            viewshed.dirty = true;
+        }
+    }
+
+    if let Some((other, (x, y))) = swapped {
+        if let Some(pos) = positions.get_mut(other) {
+            pos.x = x;
+            pos.y = y;
+        }
+        if let Some(viewshed) = viewsheds.get_mut(other) {
+            viewshed.dirty = true;
+        }
+        let name = names.get(other).map_or("it", |n| n.name.as_str());
+        ecs.write_resource::<GameLog>().say(format!("You swap places with the {}.", name));
+    }
+
+    if opened_door {
+        ecs.write_resource::<GameLog>().say("You open the door.".into());
+        // everybody who could see the door might see through it now
+        for viewshed in (&mut viewsheds).join() {
+            viewshed.dirty = true;
+        }
+    }
+}
+
+/// Closes every open door around the player, false if there werent any to close.
+fn close_doors(ecs: &mut World) -> bool {
+    let player = *ecs.fetch::<Entity>();
+    let Position { x, y } = *ecs
+        .read_storage::<Position>()
+        .get(player)
+        .expect("Player is expected to be positional");
+
+    let mut closed = false;
+    {
+        let mut map = ecs.fetch_mut::<map::TetraMap>();
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (tx, ty) = (x + dx, y + dy);
+                if tx >= 0 && ty >= 0 && tx < map.width() && ty < map.height() {
+                    closed |= map.close_door(tx, ty);
+                }
+            }
+        }
+    }
+
+    if closed {
+        ecs.write_resource::<GameLog>().say("You close the door.".into());
+        for viewshed in (&mut ecs.write_storage::<Viewshed>()).join() {
+            viewshed.dirty = true;
+        }
+    }
+    closed
+}
+
+fn player_tile(ecs: &World) -> map::TileType {
+    let player = *ecs.fetch::<Entity>();
+    let map = ecs.fetch::<map::TetraMap>();
+    let positions = ecs.read_storage::<Position>();
+    let pos = positions.get(player).expect("Player is expected to be positional");
+    *map.buffer.get(pos.x, pos.y)
+}
+
+fn pickup_item(ecs: &mut World) {
+    let player = ecs.fetch::<Entity>();
+    let player = *player;
+    let entities = ecs.entities();
+    let items = ecs.read_storage::<Item>();
+    let positions = ecs.read_storage::<Position>();
+    let mut gamelog = ecs.fetch_mut::<GameLog>();
+
+    let mut target_item: Option<Entity> = None;
+    for (item_entity, _item, position) in (&entities, &items, &positions).join() {
+        let p_pos = positions.get(player).unwrap();
+        if position.x == p_pos.x && position.y == p_pos.y {
+            target_item = Some(item_entity);
+        }
+    }
+
+    match target_item {
+        None => gamelog
+            .entries
+            .push("There is nothing here to pickup.".to_string()),
+        Some(item) => {
+            let mut pickup = ecs.write_storage::<WantsToPickupItem>();
+            pickup
+                .insert(
+                    player,
+                    WantsToPickupItem {
+                        collected_by: player,
+                        item,
+                    },
+                )
+                .expect("Could not notify of item pickup");
+        }
+    }
+}
+
+pub fn inventory_contents(ecs: &World, player: Entity) -> Vec<(Name, Entity)> {
+    let names = ecs.read_storage::<Name>();
+    let backpack = ecs.read_storage::<InBackpack>();
+    let entities = ecs.entities();
+
+    let mut items = Vec::new();
+    for (entity, _pack, name) in (&entities, &backpack, &names)
+        .join()
+        .filter(|item| item.1.owner == player)
+    {
+        items.push((name.clone(), entity))
+    }
+    items
+}
+
+/// Everything the player is wearing, in the order the remove screen shows it.
+pub fn equipped_contents(ecs: &World, player: Entity) -> Vec<(Name, Entity)> {
+    let names = ecs.read_storage::<Name>();
+    let equipped = ecs.read_storage::<Equipped>();
+    let entities = ecs.entities();
+
+    (&entities, &equipped, &names)
+        .join()
+        .filter(|item| item.1.owner == player)
+        .map(|(entity, _, name)| (name.clone(), entity))
+        .collect()
+}
+
+fn inventory_item(ecs: &World, player: Entity, slot: usize) -> Option<Entity> {
+    inventory_contents(ecs, player)
+        .get(slot)
+        .map(|(_, item)| *item)
+}
+
+/// Reverse of the inventory slot lookup, for when a menu hands us the item itself.
+pub fn inventory_slot(ecs: &World, item: Entity) -> Option<usize> {
+    let player = *ecs.fetch::<Entity>();
+    inventory_contents(ecs, player)
+        .iter()
+        .position(|(_, e)| *e == item)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::components::spawner;
+    use crate::headless::Headless;
+    use crate::systems::MapIndexingSystem;
+
+    /// Drops the named creature on an open tile next to the player, hands back
+    /// it and which way the player has to go to bump into it.
+    fn beside(game: &mut Headless, name: &str, charmed: bool) -> (Entity, (i32, i32)) {
+        let ecs = &mut game.state.ecs;
+        let player = *ecs.fetch::<Entity>();
+        let (px, py) = {
+            let pos = ecs.read_storage::<Position>();
+            let pos = pos.get(player).expect("Player has no position");
+            (pos.x, pos.y)
+        };
+        let (dx, dy) = {
+            let map = ecs.fetch::<map::TetraMap>();
+            [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (-1, -1), (1, -1), (-1, 1)]
+                .iter()
+                .copied()
+                .find(|(dx, dy)| {
+                    let (x, y) = (px + dx, py + dy);
+                    !map.is_blocked(x, y) && map.entities.get(x, y).is_empty()
+                })
+                .expect("Nowhere next to the player to put anything")
+        };
+        let creature = spawner::spawn_named(ecs, name, px + dx, py + dy, 1).expect("No such creature");
+        if charmed {
+            StatusEffects::apply(&mut ecs.write_storage(), creature, StatusKind::Charm, 10);
+        }
+        MapIndexingSystem {}.run_now(ecs);
+        (creature, (dx, dy))
+    }
+
+    fn bump(game: &mut Headless, name: &str, charmed: bool) -> (Option<Entity>, bool) {
+        let (creature, (dx, dy)) = beside(game, name, charmed);
+        let ecs = &mut game.state.ecs;
+        let player = *ecs.fetch::<Entity>();
+        let before = ecs.read_storage::<Position>().get(player).map(|p| (p.x, p.y));
+        try_move_player(dx, dy, ecs);
+        let after = ecs.read_storage::<Position>().get(player).map(|p| (p.x, p.y));
+        let target = ecs.read_storage::<WantsToMelee>().get(player).map(|w| w.target);
+        assert!(target.is_none() || target == Some(creature));
+        (target, before != after)
+    }
+
+    #[test]
+    fn bumping_a_hostile_attacks_it() {
+        let mut game = Headless::new(3);
+        let (target, moved) = bump(&mut game, "Orc", false);
+        assert!(target.is_some());
+        assert!(!moved);
+    }
+
+    #[test]
+    fn bumping_a_neutral_attacks_it() {
+        // rats ignore the player and the player ignores them, they can still be hit
+        let mut game = Headless::new(3);
+        let (target, moved) = bump(&mut game, "Rat", false);
+        assert!(target.is_some());
+        assert!(!moved);
+    }
+
+    #[test]
+    fn bumping_an_ally_swaps_places() {
+        let mut game = Headless::new(3);
+        let (target, moved) = bump(&mut game, "Orc", true);
+        assert!(target.is_none());
+        assert!(moved);
+        let log = &game.state.ecs.fetch::<GameLog>().entries;
+        assert_eq!(log.last().map(String::as_str), Some("You swap places with the Orc."));
+    }
+}
@@ -0,0 +1,614 @@
+use crate::components::{EquipmentSlot, StatusKind};
+use crate::map_builders::Prefab;
+use crate::util::{Dice, RandomTable};
+use rltk::RGB;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Where the game looks for the raws, relative to wherever it was started from.
+pub const RAWS_PATH: &str = "raws/spawns.json";
+
+// copy of the raws baked into the binary, for when theres no raws folder next to us
+const EMBEDDED_RAWS: &str = include_str!("../../raws/spawns.json");
+
+/// Prefabs live in this folder next to the spawns file, one per `.txt` file.
+pub const PREFAB_DIR: &str = "prefabs";
+
+const EMBEDDED_PREFABS: &[(&str, &str)] = &[
+    ("orc_den.txt", include_str!("../../raws/prefabs/orc_den.txt")),
+    ("shrine.txt", include_str!("../../raws/prefabs/shrine.txt")),
+    ("treasure_vault.txt", include_str!("../../raws/prefabs/treasure_vault.txt")),
+];
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Raws {
+    #[serde(default)]
+    pub monsters: Vec<MonsterRaw>,
+    #[serde(default)]
+    pub items: Vec<ItemRaw>,
+    #[serde(default)]
+    pub spawn_table: Vec<SpawnTableEntry>,
+    #[serde(default)]
+    pub factions: Vec<FactionRaw>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RenderableRaw {
+    pub glyph: String,
+    pub fg: String,
+    pub bg: String,
+    pub order: i32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct StatsRaw {
+    pub max_hp: i32,
+    pub power: i32,
+    pub defense: i32,
+    /// dice notation, like "1d6+2"
+    pub damage: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MonsterRaw {
+    pub name: String,
+    pub renderable: RenderableRaw,
+    pub stats: StatsRaw,
+    pub vision: i32,
+    /// energy per round, ACTION_COST is the usual one action a round
+    #[serde(default = "default_speed")]
+    pub speed: i32,
+    #[serde(default)]
+    pub temperament: TemperamentRaw,
+    pub ranged: Option<RangedAttackRaw>,
+    /// left out it goes in with the rest of the `DEFAULT_FACTION`
+    pub faction: Option<String>,
+}
+
+/// A monster's shot or spell, it works like a ranged item the monster never
+/// runs out of.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RangedAttackRaw {
+    pub name: String,
+    pub range: i32,
+    pub inflicts_damage: Option<i32>,
+    pub status: Option<StatusRaw>,
+    #[serde(default)]
+    pub cooldown: i32,
+    #[serde(default)]
+    pub keep_distance: i32,
+}
+
+/// Leaving it out makes for a monster that stands its ground and forgets the
+/// player as soon as they are out of sight.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct TemperamentRaw {
+    #[serde(default)]
+    pub flee_below: i32,
+    #[serde(default)]
+    pub wanders: bool,
+    #[serde(default)]
+    pub search_turns: i32,
+}
+
+/// What monsters in one faction do about somebody in another.
+#[derive(Deserialize, PartialEq, Eq, Copy, Clone, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Reaction {
+    Attack,
+    #[default]
+    Ignore,
+    Flee,
+}
+
+/// How a faction reacts to each of the others, anybody not listed gets `default`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FactionRaw {
+    pub name: String,
+    #[serde(default)]
+    pub default: Reaction,
+    #[serde(default)]
+    pub reactions: HashMap<String, Reaction>,
+}
+
+/// The faction for monsters that dont say which one they are in.
+pub const DEFAULT_FACTION: &str = "Monsters";
+
+fn default_speed() -> i32 {
+    crate::components::ACTION_COST
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct ItemEffectsRaw {
+    pub provides_healing: Option<i32>,
+    pub ranged: Option<i32>,
+    pub inflicts_damage: Option<i32>,
+    pub area_of_effect: Option<i32>,
+    pub status: Option<StatusRaw>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct StatusRaw {
+    pub kind: StatusKind,
+    pub turns: i32,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ItemRaw {
+    pub name: String,
+    pub renderable: RenderableRaw,
+    #[serde(default)]
+    pub consumable: bool,
+    #[serde(default)]
+    pub effects: ItemEffectsRaw,
+    pub equippable: Option<EquippableRaw>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct EquippableRaw {
+    pub slot: EquipmentSlot,
+    #[serde(default)]
+    pub power_bonus: i32,
+    #[serde(default)]
+    pub defense_bonus: i32,
+    /// dice notation, only for melee weapons
+    pub damage: Option<String>,
+}
+
+/// How likely something is to show up, and on which levels.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SpawnTableEntry {
+    pub name: String,
+    pub weight: i32,
+    #[serde(default = "first_level")]
+    pub min_depth: i32,
+    /// No max_depth means it keeps showing up all the way down.
+    pub max_depth: Option<i32>,
+}
+
+fn first_level() -> i32 {
+    1
+}
+
+impl SpawnTableEntry {
+    pub fn spawns_at(&self, depth: i32) -> bool {
+        depth >= self.min_depth && self.max_depth.is_none_or(|max| depth <= max)
+    }
+}
+
+#[derive(Debug)]
+pub enum RawError {
+    Io(std::io::Error),
+    /// The json itself is broken, serde knows the line and column.
+    Parse(serde_json::Error),
+    /// The json is fine but one of the entries makes no sense.
+    Invalid { entry: String, reason: String },
+}
+
+impl fmt::Display for RawError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RawError::Io(e) => write!(f, "Unable to read the raws: {}", e),
+            RawError::Parse(e) => write!(f, "Unable to parse the raws: {}", e),
+            RawError::Invalid { entry, reason } => write!(f, "Bad raws entry {}: {}", entry, reason),
+        }
+    }
+}
+
+impl std::error::Error for RawError {}
+
+/// All of the entity templates, looked up by their name.
+pub struct RawMaster {
+    pub raws: Raws,
+    pub prefabs: Vec<Prefab>,
+    monster_index: HashMap<String, usize>,
+    item_index: HashMap<String, usize>,
+    faction_index: HashMap<String, usize>,
+}
+
+impl RawMaster {
+    pub fn parse(json: &str) -> Result<RawMaster, RawError> {
+        let raws: Raws = serde_json::from_str(json).map_err(RawError::Parse)?;
+        RawMaster::new(raws)
+    }
+
+    /// The spawns file, plus any prefabs in the `PREFAB_DIR` folder next to it.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<RawMaster, RawError> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path).map_err(RawError::Io)?;
+        let raws = RawMaster::parse(&json)?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(".")).join(PREFAB_DIR);
+        if !dir.is_dir() {
+            return Ok(raws);
+        }
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&dir).map_err(RawError::Io)? {
+            let path = entry.map_err(RawError::Io)?.path();
+            if path.extension().is_some_and(|ext| ext == "txt") {
+                files.push(path);
+            }
+        }
+        // sorted, the order they get rolled for has to be the same every time
+        files.sort();
+
+        let mut prefabs = Vec::new();
+        for file in files {
+            let text = fs::read_to_string(&file).map_err(RawError::Io)?;
+            prefabs.push((file.display().to_string(), text));
+        }
+        raws.with_prefabs(prefabs.iter().map(|(f, t)| (f.as_str(), t.as_str())))
+    }
+
+    /// The raws that shipped with the game.
+    pub fn embedded() -> RawMaster {
+        RawMaster::parse(EMBEDDED_RAWS)
+            .and_then(|raws| raws.with_prefabs(EMBEDDED_PREFABS.iter().copied()))
+            .expect("The embedded raws are broken")
+    }
+
+    /// Adds the prefabs, given as (file name, contents).
+    pub fn with_prefabs<'a, I>(mut self, files: I) -> Result<RawMaster, RawError>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        for (file, text) in files {
+            let entry = || format!("prefab {}", file);
+            let prefab = Prefab::parse(text).map_err(|reason| invalid(entry(), reason))?;
+            for name in prefab.legend.values() {
+                if self.monster(name).is_none() && self.item(name).is_none() {
+                    return Err(invalid(
+                        entry(),
+                        format!("there is no monster or item called \"{}\"", name),
+                    ));
+                }
+            }
+            self.prefabs.push(prefab);
+        }
+        Ok(self)
+    }
+
+    /// The raws from `RAWS_PATH` if there are any, otherwise the ones we shipped with.
+    pub fn load() -> Result<RawMaster, RawError> {
+        if Path::new(RAWS_PATH).exists() {
+            RawMaster::from_file(RAWS_PATH)
+        } else {
+            Ok(RawMaster::embedded())
+        }
+    }
+
+    pub fn new(raws: Raws) -> Result<RawMaster, RawError> {
+        let mut monster_index = HashMap::new();
+        let mut item_index = HashMap::new();
+        let mut faction_index = HashMap::new();
+
+        for (i, faction) in raws.factions.iter().enumerate() {
+            if faction_index.insert(faction.name.clone(), i).is_some() {
+                let entry = format!("factions[{}] \"{}\"", i, faction.name);
+                return Err(invalid(entry, "there is already a faction with this name".into()));
+            }
+        }
+        for (i, faction) in raws.factions.iter().enumerate() {
+            let entry = || format!("factions[{}] \"{}\"", i, faction.name);
+            // sorted, so its always the same one that gets complained about
+            let mut others: Vec<&String> = faction.reactions.keys().collect();
+            others.sort();
+            if let Some(other) = others.into_iter().find(|other| !faction_index.contains_key(*other)) {
+                return Err(invalid(entry(), format!("there is no faction called \"{}\"", other)));
+            }
+        }
+
+        for (i, monster) in raws.monsters.iter().enumerate() {
+            let entry = || format!("monsters[{}] \"{}\"", i, monster.name);
+            validate_renderable(&monster.renderable).map_err(|reason| invalid(entry(), reason))?;
+            if monster.stats.max_hp < 1 {
+                return Err(invalid(entry(), "max_hp has to be at least 1".into()));
+            }
+            Dice::parse(&monster.stats.damage).map_err(|reason| invalid(entry(), reason))?;
+            if monster.vision < 1 {
+                return Err(invalid(entry(), "vision has to be at least 1".into()));
+            }
+            if monster.speed < 1 {
+                return Err(invalid(entry(), "speed has to be at least 1".into()));
+            }
+            if !(0..=100).contains(&monster.temperament.flee_below) {
+                return Err(invalid(entry(), "flee_below is a percentage, it has to be 0 to 100".into()));
+            }
+            if monster.temperament.search_turns < 0 {
+                return Err(invalid(entry(), "search_turns cant be negative".into()));
+            }
+            if let Some(ranged) = &monster.ranged {
+                if ranged.range < 1 {
+                    return Err(invalid(entry(), "a ranged attack needs a range of at least 1".into()));
+                }
+                if ranged.inflicts_damage.is_none() && ranged.status.is_none() {
+                    return Err(invalid(entry(), "a ranged attack has to do damage or inflict a status".into()));
+                }
+                if ranged.status.as_ref().is_some_and(|s| s.turns < 1) {
+                    return Err(invalid(entry(), "status effects have to last at least 1 turn".into()));
+                }
+                if ranged.cooldown < 0 {
+                    return Err(invalid(entry(), "cooldown cant be negative".into()));
+                }
+                if ranged.keep_distance < 0 || ranged.keep_distance > ranged.range {
+                    return Err(invalid(entry(), "keep_distance has to be between 0 and the range".into()));
+                }
+            }
+            if let Some(faction) = &monster.faction {
+                if !faction_index.contains_key(faction) {
+                    return Err(invalid(entry(), format!("there is no faction called \"{}\"", faction)));
+                }
+            }
+            if monster_index.insert(monster.name.clone(), i).is_some() {
+                return Err(invalid(entry(), "there is already a monster with this name".into()));
+            }
+        }
+
+        for (i, item) in raws.items.iter().enumerate() {
+            let entry = || format!("items[{}] \"{}\"", i, item.name);
+            validate_renderable(&item.renderable).map_err(|reason| invalid(entry(), reason))?;
+            let effects = &item.effects;
+            if effects.ranged.is_some_and(|range| range < 1) {
+                return Err(invalid(entry(), "ranged items need a range of at least 1".into()));
+            }
+            if effects.ranged.is_some() && effects.inflicts_damage.is_none() && effects.status.is_none() {
+                return Err(invalid(entry(), "ranged items need something to inflict".into()));
+            }
+            if effects.status.as_ref().is_some_and(|s| s.turns < 1) {
+                return Err(invalid(entry(), "status effects have to last at least 1 turn".into()));
+            }
+            if effects.area_of_effect.is_some() && effects.ranged.is_none() {
+                return Err(invalid(entry(), "area_of_effect only works on ranged items".into()));
+            }
+            if item.equippable.is_some() && item.consumable {
+                return Err(invalid(entry(), "equippable items can't be consumable".into()));
+            }
+            if let Some(EquippableRaw { slot, damage: Some(damage), .. }) = &item.equippable {
+                if *slot != EquipmentSlot::Melee {
+                    return Err(invalid(entry(), "only melee weapons do damage".into()));
+                }
+                Dice::parse(damage).map_err(|reason| invalid(entry(), reason))?;
+            }
+            if item_index.insert(item.name.clone(), i).is_some() {
+                return Err(invalid(entry(), "there is already an item with this name".into()));
+            }
+        }
+
+        for (i, spawn) in raws.spawn_table.iter().enumerate() {
+            let entry = || format!("spawn_table[{}] \"{}\"", i, spawn.name);
+            if !monster_index.contains_key(&spawn.name) && !item_index.contains_key(&spawn.name) {
+                return Err(invalid(entry(), "there is no monster or item with this name".into()));
+            }
+            if spawn.weight < 1 {
+                return Err(invalid(entry(), "weight has to be at least 1".into()));
+            }
+            if spawn.min_depth < 1 {
+                return Err(invalid(entry(), "min_depth has to be at least 1".into()));
+            }
+            if spawn.max_depth.is_some_and(|max| max < spawn.min_depth) {
+                return Err(invalid(entry(), "max_depth is below min_depth".into()));
+            }
+        }
+
+        Ok(RawMaster {
+            raws,
+            prefabs: Vec::new(),
+            monster_index,
+            item_index,
+            faction_index,
+        })
+    }
+
+    pub fn monster(&self, name: &str) -> Option<&MonsterRaw> {
+        self.monster_index.get(name).map(|i| &self.raws.monsters[*i])
+    }
+
+    pub fn item(&self, name: &str) -> Option<&ItemRaw> {
+        self.item_index.get(name).map(|i| &self.raws.items[*i])
+    }
+
+    /// What somebody in faction `mine` does about somebody in `theirs`. A faction
+    /// the raws dont know anything about sticks to its own and attacks the rest.
+    pub fn reaction(&self, mine: &str, theirs: &str) -> Reaction {
+        match self.faction_index.get(mine) {
+            Some(i) => {
+                let faction = &self.raws.factions[*i];
+                faction.reactions.get(theirs).copied().unwrap_or(faction.default)
+            }
+            None if mine == theirs => Reaction::Ignore,
+            None => Reaction::Attack,
+        }
+    }
+
+    /// The monsters that can turn up at this depth, weighted by the spawn table.
+    pub fn monster_table(&self, depth: i32) -> RandomTable {
+        self.spawn_table(depth, |name| self.monster_index.contains_key(name))
+    }
+
+    /// The items that can turn up at this depth, weighted by the spawn table.
+    pub fn item_table(&self, depth: i32) -> RandomTable {
+        self.spawn_table(depth, |name| self.item_index.contains_key(name))
+    }
+
+    fn spawn_table<F: Fn(&str) -> bool>(&self, depth: i32, wanted: F) -> RandomTable {
+        self.raws
+            .spawn_table
+            .iter()
+            .filter(|spawn| spawn.spawns_at(depth) && wanted(&spawn.name))
+            .fold(RandomTable::new(), |table, spawn| {
+                table.add(&spawn.name, spawn.weight)
+            })
+    }
+}
+
+impl StatsRaw {
+    pub fn damage(&self) -> Dice {
+        Dice::parse(&self.damage).expect("Monster damage gets checked when the raws are loaded")
+    }
+}
+
+impl EquippableRaw {
+    pub fn damage(&self) -> Option<Dice> {
+        self.damage
+            .as_ref()
+            .map(|damage| Dice::parse(damage).expect("Weapon damage gets checked when the raws are loaded"))
+    }
+}
+
+impl RenderableRaw {
+    pub fn glyph(&self) -> rltk::FontCharType {
+        rltk::to_cp437(self.glyph.chars().next().unwrap_or('?'))
+    }
+
+    pub fn fg(&self) -> RGB {
+        RGB::from_hex(&self.fg).unwrap_or_else(|_| RGB::named(rltk::MAGENTA))
+    }
+
+    pub fn bg(&self) -> RGB {
+        RGB::from_hex(&self.bg).unwrap_or_else(|_| RGB::named(rltk::BLACK))
+    }
+}
+
+fn invalid(entry: String, reason: String) -> RawError {
+    RawError::Invalid { entry, reason }
+}
+
+fn validate_renderable(renderable: &RenderableRaw) -> Result<(), String> {
+    if renderable.glyph.chars().count() != 1 {
+        return Err(format!(
+            "glyph has to be a single character, not \"{}\"",
+            renderable.glyph
+        ));
+    }
+    for (field, colour) in [("fg", &renderable.fg), ("bg", &renderable.bg)].iter() {
+        if RGB::from_hex(colour).is_err() {
+            return Err(format!(
+                "{} has to be a colour like \"#FF00FF\", not \"{}\"",
+                field, colour
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    /// Just enough raws for every kind of entry to have one.
+    fn raws() -> Value {
+        json!({
+            "monsters": [{
+                "name": "Orc",
+                "renderable": { "glyph": "o", "fg": "#FF0000", "bg": "#000000", "order": 1 },
+                "stats": { "max_hp": 16, "power": 4, "defense": 1, "damage": "1d6" },
+                "vision": 8,
+                "faction": "Orcs"
+            }],
+            "items": [{
+                "name": "Magic Missile Scroll",
+                "renderable": { "glyph": ")", "fg": "#00FFFF", "bg": "#000000", "order": 2 },
+                "consumable": true,
+                "effects": { "ranged": 6, "inflicts_damage": 8 }
+            }],
+            "spawn_table": [
+                { "name": "Orc", "weight": 10 },
+                { "name": "Magic Missile Scroll", "weight": 4 }
+            ],
+            "factions": [
+                { "name": "Orcs", "default": "attack", "reactions": { "Orcs": "ignore" } }
+            ]
+        })
+    }
+
+    /// Which entry the raws got turned down for, and why.
+    fn rejected(raws: Value) -> (String, String) {
+        match RawMaster::parse(&raws.to_string()) {
+            Err(RawError::Invalid { entry, reason }) => (entry, reason),
+            Err(e) => panic!("Rejected for the wrong thing: {}", e),
+            Ok(_) => panic!("Bad raws got through"),
+        }
+    }
+
+    #[test]
+    fn accepts_good_raws() {
+        assert!(RawMaster::parse(&raws().to_string()).is_ok());
+    }
+
+    #[test]
+    fn accepts_the_shipped_raws() {
+        let raws = RawMaster::embedded();
+        assert!(!raws.raws.monsters.is_empty());
+        assert!(!raws.prefabs.is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_spawn_name() {
+        let mut raws = raws();
+        raws["spawn_table"][1]["name"] = json!("Magic Misile Scroll");
+        let (entry, reason) = rejected(raws);
+        assert_eq!(entry, "spawn_table[1] \"Magic Misile Scroll\"");
+        assert!(reason.contains("no monster or item"), "{}", reason);
+    }
+
+    #[test]
+    fn rejects_bad_faction_reference() {
+        let mut raws = raws();
+        raws["monsters"][0]["faction"] = json!("Orks");
+        let (entry, reason) = rejected(raws);
+        assert_eq!(entry, "monsters[0] \"Orc\"");
+        assert!(reason.contains("\"Orks\""), "{}", reason);
+
+        let mut raws = self::raws();
+        raws["factions"][0]["reactions"]["Elves"] = json!("flee");
+        let (entry, reason) = rejected(raws);
+        assert_eq!(entry, "factions[0] \"Orcs\"");
+        assert!(reason.contains("\"Elves\""), "{}", reason);
+    }
+
+    #[test]
+    fn rejects_bad_dice() {
+        let mut raws = raws();
+        raws["monsters"][0]["stats"]["damage"] = json!("1d");
+        let (entry, reason) = rejected(raws);
+        assert_eq!(entry, "monsters[0] \"Orc\"");
+        assert!(reason.contains("\"1d\""), "{}", reason);
+    }
+
+    #[test]
+    fn rejects_max_depth_below_min_depth() {
+        let mut raws = raws();
+        raws["spawn_table"][0]["min_depth"] = json!(4);
+        raws["spawn_table"][0]["max_depth"] = json!(2);
+        let (entry, reason) = rejected(raws);
+        assert_eq!(entry, "spawn_table[0] \"Orc\"");
+        assert_eq!(reason, "max_depth is below min_depth");
+    }
+
+    #[test]
+    fn rejects_ranged_items_without_range() {
+        for range in [0, -3] {
+            let mut raws = raws();
+            raws["items"][0]["effects"]["ranged"] = json!(range);
+            let (entry, reason) = rejected(raws);
+            assert_eq!(entry, "items[0] \"Magic Missile Scroll\"");
+            assert!(reason.contains("range"), "{}", reason);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use crate::components::*;
+use crate::headless::Headless;
+use crate::player::Command;
+use crate::raws::RawMaster;
+use crate::State;
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// First line of a replay file, everything needed to start the same game again.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Header {
+    pub version: String,
+    pub seed: u64,
+}
+
+/// One accepted command, and what the world looked like once it played out.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub turn: u64,
+    pub command: Command,
+    pub digest: Digest,
+}
+
+/// Cheap fingerprint of the world, good enough to tell when a replay went off the rails.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Digest {
+    pub turn: u64,
+    pub player: (i32, i32),
+    pub hp: i32,
+    pub entities: usize,
+    pub log_entries: usize,
+    pub checksum: u64,
+}
+
+impl Digest {
+    pub fn of(ecs: &World) -> Digest {
+        let player = *ecs.fetch::<Entity>();
+        let entities = ecs.entities();
+        let positions = ecs.read_storage::<Position>();
+        let stats = ecs.read_storage::<CombatStats>();
+        let backpacks = ecs.read_storage::<InBackpack>();
+        let equipped = ecs.read_storage::<Equipped>();
+
+        let mut checksum = FNV_OFFSET;
+        for (e, pos) in (&entities, &positions).join() {
+            checksum = fnv(checksum, &[e.id() as i64, pos.x as i64, pos.y as i64]);
+        }
+        for (e, stats) in (&entities, &stats).join() {
+            checksum = fnv(checksum, &[e.id() as i64, stats.hp as i64]);
+        }
+        for (e, pack) in (&entities, &backpacks).join() {
+            checksum = fnv(checksum, &[e.id() as i64, pack.owner.id() as i64]);
+        }
+        for (e, worn) in (&entities, &equipped).join() {
+            checksum = fnv(checksum, &[e.id() as i64, worn.owner.id() as i64]);
+        }
+
+        Digest {
+            turn: ecs.fetch::<Turn>().0,
+            player: positions.get(player).map_or((-1, -1), |p| (p.x, p.y)),
+            hp: stats.get(player).map_or(0, |s| s.hp),
+            entities: entities.join().count(),
+            log_entries: ecs.fetch::<GameLog>().entries.len(),
+            checksum,
+        }
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+// fnv-1a, hand rolled so the checksum doesnt change under us between rust versions
+fn fnv(mut hash: u64, values: &[i64]) -> u64 {
+    for value in values {
+        for byte in value.to_le_bytes().iter() {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// Writes every accepted command out to a replay file as the game goes.
+pub struct Recorder {
+    out: BufWriter<File>,
+    pending: Option<(u64, Command)>,
+}
+
+impl Recorder {
+    pub fn create<P: AsRef<Path>>(path: P, seed: u64) -> io::Result<Recorder> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let header = Header {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            seed,
+        };
+        serde_json::to_writer(&mut out, &header)?;
+        writeln!(out)?;
+        out.flush()?;
+        Ok(Recorder { out, pending: None })
+    }
+
+    /// The command gets written out once the world has settled after it.
+    pub fn command(&mut self, turn: u64, command: Command) {
+        self.pending = Some((turn, command));
+    }
+
+    /// The world is waiting on the player again, so whatever the last command
+    /// did has fully played out.
+    pub fn settled(&mut self, ecs: &World) -> io::Result<()> {
+        if let Some((turn, command)) = self.pending.take() {
+            let entry = Entry {
+                turn,
+                command,
+                digest: Digest::of(ecs),
+            };
+            serde_json::to_writer(&mut self.out, &entry)?;
+            writeln!(self.out)?;
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+pub fn read_replay<P: AsRef<Path>>(path: P) -> io::Result<(Header, Vec<Entry>)> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header = match lines.next() {
+        Some(line) => serde_json::from_str::<Header>(&line?)?,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Replay file is empty",
+            ))
+        }
+    };
+
+    let mut entries = Vec::new();
+    for line in lines {
+        let line = line?;
+        if !line.trim().is_empty() {
+            entries.push(serde_json::from_str::<Entry>(&line)?);
+        }
+    }
+    Ok((header, entries))
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Diverged {
+        turn: u64,
+        expected: Box<Digest>,
+        actual: Box<Digest>,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(e) => write!(f, "Unable to read the replay: {}", e),
+            ReplayError::Diverged {
+                turn,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Replay diverged at turn {}, expected {:?} but got {:?}",
+                turn, expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(e: io::Error) -> Self {
+        ReplayError::Io(e)
+    }
+}
+
+/// Plays the recorded game back without a window, checking the world against
+/// the recording after every command. Hands back the finished game. The raws
+/// have to be the ones the game was recorded with.
+pub fn replay<P: AsRef<Path>>(path: P, raws: RawMaster) -> Result<Headless, ReplayError> {
+    let (header, entries) = read_replay(path)?;
+    if header.version != env!("CARGO_PKG_VERSION") {
+        log::warn!(
+            "Replay was recorded with version {}, it might not play back the same",
+            header.version
+        );
+    }
+
+    let mut game = Headless::from_state(State::with_raws(header.seed, raws));
+    for entry in entries {
+        game.step(entry.command);
+        let actual = Digest::of(&game.state.ecs);
+        if actual != entry.digest {
+            return Err(ReplayError::Diverged {
+                turn: entry.turn,
+                expected: Box::new(entry.digest),
+                actual: Box::new(actual),
+            });
+        }
+    }
+    Ok(game)
+}
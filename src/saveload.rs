@@ -0,0 +1,453 @@
+use crate::components::*;
+use crate::draw;
+use crate::systems;
+use crate::util;
+use crate::RunState;
+use specs::{
+    error::NoError,
+    prelude::*,
+    saveload::{
+        ConvertSaveload, DeserializeComponents, MarkedBuilder, Marker, SerializeComponents,
+        SimpleMarker, SimpleMarkerAllocator,
+    },
+};
+use serde::{Deserialize, Serialize};
+use specs_derive::*;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const SAVE_PATH: &str = "./savegame.json";
+const BROKEN_SAVE_PATH: &str = "./savegame.broken.json";
+
+/// Tag for every entity that should end up in the save file.
+pub struct SerializeMe;
+
+/// Resources dont go through the saveload machinery, so they ride along on a
+/// throwaway entity while saving.
+#[derive(Component, ConvertSaveload, Clone)]
+pub struct SerializationHelper {
+    pub map: map::TetraMap,
+    pub log: GameLog,
+    pub run_state: RunState,
+    pub seed: u64,
+    /// Where each of the streams got up to, so a loaded game rolls what it would have.
+    pub map_rng: util::StreamRng,
+    pub spawn_rng: util::StreamRng,
+    pub combat_rng: util::StreamRng,
+    pub turn: Turn,
+    pub depth: Depth,
+}
+
+// Each storage gets serialized on its own, the tuple impls in specs dont go
+// anywhere near the amount of components we have.
+macro_rules! serialize_individually {
+    ($ecs:expr, $ser:expr, $data:expr, $( $type:ty),*) => {
+        $(
+        SerializeComponents::<NoError, SimpleMarker<SerializeMe>>::serialize(
+            &( $ecs.read_storage::<$type>(), ),
+            &$data.0,
+            &$data.1,
+            &mut $ser,
+        )?;
+        )*
+    };
+}
+
+macro_rules! deserialize_individually {
+    ($ecs:expr, $de:expr, $data:expr, $( $type:ty),*) => {
+        $(
+        DeserializeComponents::<NoError, _>::deserialize(
+            &mut ( &mut $ecs.write_storage::<$type>(), ),
+            &$data.0,
+            &mut $data.1,
+            &mut $data.2,
+            &mut $de,
+        )?;
+        )*
+    };
+}
+
+pub fn does_save_exist() -> bool {
+    Path::new(SAVE_PATH).exists()
+}
+
+pub fn save_game(ecs: &mut World) -> io::Result<()> {
+    save_to(ecs, fs::File::create(SAVE_PATH)?)
+}
+
+fn save_to<W: Write>(ecs: &mut World, writer: W) -> io::Result<()> {
+    let helper = {
+        let map = (*ecs.fetch::<map::TetraMap>()).clone();
+        let log = (*ecs.fetch::<GameLog>()).clone();
+        let run_state = *ecs.fetch::<RunState>();
+        let seed = ecs.fetch::<util::Seed>().0;
+        let map_rng = ecs.fetch::<util::MapRng>().0.clone();
+        let spawn_rng = ecs.fetch::<util::SpawnRng>().0.clone();
+        let combat_rng = (*ecs.fetch::<util::RngResource>()).clone();
+        let turn = *ecs.fetch::<Turn>();
+        let depth = *ecs.fetch::<Depth>();
+        ecs.create_entity()
+            .with(SerializationHelper {
+                map,
+                log,
+                run_state,
+                seed,
+                map_rng,
+                spawn_rng,
+                combat_rng,
+                turn,
+                depth,
+            })
+            .marked::<SimpleMarker<SerializeMe>>()
+            .build()
+    };
+
+    let res = write_save(ecs, writer);
+
+    ecs.delete_entity(helper)
+        .expect("Unable to delete the serialization helper");
+    res
+}
+
+fn write_save<W: Write>(ecs: &World, writer: W) -> io::Result<()> {
+    let data = (
+        ecs.entities(),
+        ecs.read_storage::<SimpleMarker<SerializeMe>>(),
+    );
+
+    let mut serializer = serde_json::Serializer::new(writer);
+    serialize_individually!(
+        ecs,
+        serializer,
+        data,
+        Position,
+        draw::Renderable,
+        Name,
+        Viewshed,
+        Monster,
+        Player,
+        CombatStats,
+        BlocksTile,
+        WantsToMelee,
+        WantsToPickupItem,
+        SufferDamage,
+        ProvidesHealing,
+        Item,
+        InBackpack,
+        Consumable,
+        WantsToUseItem,
+        WantsToDropItem,
+        InflictsDamage,
+        Ranged,
+        AreaOfEffect,
+        Equippable,
+        Equipped,
+        MeleePowerBonus,
+        DefenseBonus,
+        WantsToRemoveItem,
+        StatusEffects,
+        GrantsStatus,
+        Energy,
+        Temperament,
+        AiState,
+        RangedAttack,
+        Faction,
+        MeleeDamage,
+        WantsToOpenDoor,
+        SerializationHelper
+    );
+    Ok(())
+}
+
+/// Replace everything in the world with the contents of the save file. The
+/// save gets read into a world of its own first, so if its broken the game we
+/// already had is left alone.
+pub fn load_game(ecs: &mut World) -> io::Result<()> {
+    load_from(ecs, &fs::read_to_string(SAVE_PATH)?)
+}
+
+fn load_from(ecs: &mut World, save: &str) -> io::Result<()> {
+    let mut loaded = World::new();
+    crate::register_components(&mut loaded);
+    loaded.insert(SimpleMarkerAllocator::<SerializeMe>::new());
+    read_save(&mut loaded, save)?;
+
+    // the rest isnt in the save, it carries over or starts over
+    let raws = ecs
+        .remove::<crate::raws::RawMaster>()
+        .expect("The world has no raws to load the game with");
+    loaded.insert(raws);
+    loaded.insert(KilledBy(None));
+    loaded.insert(systems::FlowMaps::default());
+    *ecs = loaded;
+
+    // the entity index on the map isnt saved, fill it back in
+    systems::MapIndexingSystem {}.run_now(ecs);
+    Ok(())
+}
+
+/// Gets rid of the save, if there is one.
+pub fn delete_save() -> io::Result<()> {
+    match fs::remove_file(SAVE_PATH) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+/// Moves a save that wouldnt load out of the way, so it doesnt get offered
+/// again. Hands back where it ended up.
+pub fn set_aside_save() -> io::Result<PathBuf> {
+    let path = PathBuf::from(BROKEN_SAVE_PATH);
+    fs::rename(SAVE_PATH, &path)?;
+    Ok(path)
+}
+
+fn read_save(ecs: &mut World, save: &str) -> io::Result<()> {
+    let mut de = serde_json::Deserializer::from_str(save);
+    {
+        let mut data = (
+            &mut ecs.entities(),
+            &mut ecs.write_storage::<SimpleMarker<SerializeMe>>(),
+            &mut ecs.write_resource::<SimpleMarkerAllocator<SerializeMe>>(),
+        );
+        deserialize_individually!(
+            ecs,
+            de,
+            data,
+            Position,
+            draw::Renderable,
+            Name,
+            Viewshed,
+            Monster,
+            Player,
+            CombatStats,
+            BlocksTile,
+            WantsToMelee,
+            WantsToPickupItem,
+            SufferDamage,
+            ProvidesHealing,
+            Item,
+            InBackpack,
+            Consumable,
+            WantsToUseItem,
+            WantsToDropItem,
+            InflictsDamage,
+            Ranged,
+            AreaOfEffect,
+            Equippable,
+            Equipped,
+            MeleePowerBonus,
+            DefenseBonus,
+            WantsToRemoveItem,
+            StatusEffects,
+            GrantsStatus,
+            Energy,
+            Temperament,
+            AiState,
+            RangedAttack,
+            Faction,
+            MeleeDamage,
+            WantsToOpenDoor,
+            SerializationHelper
+        );
+    }
+
+    let (helper, player) = {
+        let entities = ecs.entities();
+        let helpers = ecs.read_storage::<SerializationHelper>();
+        let players = ecs.read_storage::<Player>();
+        (
+            (&entities, &helpers)
+                .join()
+                .map(|(e, h)| (e, h.clone()))
+                .next(),
+            (&entities, &players).join().map(|(e, _)| e).next(),
+        )
+    };
+
+    let (helper, resources) = helper.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Save file is missing the world resources")
+    })?;
+    let SerializationHelper { mut map, log, run_state, seed, map_rng, spawn_rng, combat_rng, turn, depth } = resources;
+    let player = player.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "Save file does not have a player")
+    })?;
+
+    map.rebuild_buffers();
+    ecs.insert(map);
+    ecs.insert(log);
+    ecs.insert(run_state);
+    ecs.insert(turn);
+    ecs.insert(depth);
+    ecs.insert(player);
+    ecs.insert(util::Seed(seed));
+    ecs.insert(util::MapRng(map_rng));
+    ecs.insert(util::SpawnRng(spawn_rng));
+    ecs.insert::<util::RngResource>(combat_rng);
+    ecs.delete_entity(helper)
+        .expect("Unable to delete the serialization helper");
+    ecs.maintain();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::headless::Headless;
+    use crate::player::Command;
+    use crate::State;
+
+    fn wander(n: usize) -> impl Iterator<Item = Command> {
+        let steps = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+        (0..n).map(move |i| match i % 5 {
+            4 => Command::Wait,
+            _ => {
+                let (dx, dy) = steps[(i / 3) % steps.len()];
+                Command::Move { dx, dy }
+            }
+        })
+    }
+
+    /// Turn, log, and the name, spot and hp of every creature.
+    type Snapshot = (u64, Vec<String>, Vec<(String, i32, i32, i32)>);
+
+    /// Everything about the world worth comparing that doesnt depend on entity ids.
+    fn snapshot(ecs: &World) -> Snapshot {
+        let names = ecs.read_storage::<Name>();
+        let positions = ecs.read_storage::<Position>();
+        let stats = ecs.read_storage::<CombatStats>();
+        let mut creatures: Vec<_> = (&names, &positions, &stats)
+            .join()
+            .map(|(name, pos, stats)| (name.name.clone(), pos.x, pos.y, stats.hp))
+            .collect();
+        creatures.sort();
+        (ecs.fetch::<Turn>().0, ecs.fetch::<GameLog>().entries.clone(), creatures)
+    }
+
+    fn save(ecs: &mut World) -> String {
+        let mut save = Vec::new();
+        save_to(ecs, &mut save).expect("Unable to save");
+        String::from_utf8(save).expect("Save isnt utf8")
+    }
+
+    fn load(seed: u64, save: &str) -> Headless {
+        // a different game underneath, so nothing can leak through from it
+        let mut state = State::new(seed + 1);
+        load_from(&mut state.ecs, save).expect("Unable to load");
+        Headless::from_state(state)
+    }
+
+    /// Puts the player on the stairs and takes them down, the new level gets
+    /// built out of entity ids the old one freed up.
+    fn descend(game: &mut Headless) {
+        let stairs = {
+            let map = game.state.ecs.fetch::<map::TetraMap>();
+            let idx = map.buffer.data.iter().position(|t| *t == map::TileType::DownStairs).expect("No stairs down");
+            map.xy(idx)
+        };
+        let player = *game.state.ecs.fetch::<Entity>();
+        let mut positions = game.state.ecs.write_storage::<Position>();
+        let pos = positions.get_mut(player).expect("Player has no position");
+        pos.x = stairs.0;
+        pos.y = stairs.1;
+        drop(positions);
+        game.step(Command::Descend);
+    }
+
+    fn pack(ecs: &World) -> Vec<String> {
+        let player = *ecs.fetch::<Entity>();
+        let names = ecs.read_storage::<Name>();
+        let backpacks = ecs.read_storage::<InBackpack>();
+        let mut pack: Vec<_> = (&names, &backpacks)
+            .join()
+            .filter(|(_, pack)| pack.owner == player)
+            .map(|(name, _)| name.name.clone())
+            .collect();
+        pack.sort();
+        pack
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut game = Headless::new(21);
+        descend(&mut game);
+        game.run(wander(10));
+
+        // pick up a couple of things and take a knock, so theres more than the defaults to lose
+        {
+            let ecs = &mut game.state.ecs;
+            let player = *ecs.fetch::<Entity>();
+            let items: Vec<Entity> = {
+                let entities = ecs.entities();
+                let items = ecs.read_storage::<Item>();
+                let positions = ecs.read_storage::<Position>();
+                (&entities, &items, &positions).join().map(|(e, _, _)| e).take(2).collect()
+            };
+            assert!(!items.is_empty(), "Nothing on the level to pick up");
+            for item in items {
+                ecs.write_storage::<Position>().remove(item);
+                ecs.write_storage::<InBackpack>()
+                    .insert(item, InBackpack { owner: player })
+                    .expect("Unable to insert into the backpack");
+            }
+            ecs.write_storage::<CombatStats>().get_mut(player).expect("Player has no stats").hp -= 3;
+        }
+
+        let saved = save(&mut game.state.ecs);
+        let loaded = load(21, &saved);
+        let (before, after) = (&game.state.ecs, &loaded.state.ecs);
+
+        let (map, loaded_map) = (before.fetch::<map::TetraMap>(), after.fetch::<map::TetraMap>());
+        assert_eq!(map.buffer.width, loaded_map.buffer.width);
+        assert_eq!(map.buffer.height, loaded_map.buffer.height);
+        assert!(map.buffer.data == loaded_map.buffer.data, "Map tiles changed");
+        assert_eq!(map.rooms.len(), loaded_map.rooms.len());
+
+        let (player, loaded_player) = (*before.fetch::<Entity>(), *after.fetch::<Entity>());
+        let stats = before.read_storage::<CombatStats>();
+        let loaded_stats = after.read_storage::<CombatStats>();
+        let (stats, loaded_stats) = (stats.get(player).unwrap(), loaded_stats.get(loaded_player).unwrap());
+        assert_eq!(
+            (stats.hp, stats.max_hp, stats.defense, stats.power),
+            (loaded_stats.hp, loaded_stats.max_hp, loaded_stats.defense, loaded_stats.power)
+        );
+        let positions = before.read_storage::<Position>();
+        let loaded_positions = after.read_storage::<Position>();
+        let (pos, loaded_pos) = (positions.get(player).unwrap(), loaded_positions.get(loaded_player).unwrap());
+        assert_eq!((pos.x, pos.y), (loaded_pos.x, loaded_pos.y));
+
+        assert_eq!(pack(before), pack(after));
+        assert_eq!(*before.fetch::<Depth>(), *after.fetch::<Depth>());
+        assert_eq!(*after.fetch::<Depth>(), Depth(2));
+        assert_eq!(*before.fetch::<Turn>(), *after.fetch::<Turn>());
+    }
+
+    #[test]
+    fn loaded_game_plays_on_the_same() {
+        for seed in [11, 12, 13] {
+            let mut game = Headless::new(seed);
+            game.run(wander(30));
+            let save = save(&mut game.state.ecs);
+            let mut loaded = load(seed, &save);
+
+            // the next level gets built out of whatever entity ids each game has free
+            descend(&mut game);
+            descend(&mut loaded);
+            for (i, command) in wander(60).enumerate() {
+                if game.player_dead() {
+                    break;
+                }
+                game.step(command);
+                loaded.step(command);
+                assert_eq!(
+                    snapshot(&game.state.ecs),
+                    snapshot(&loaded.state.ecs),
+                    "seed {} went different {} commands after the load",
+                    seed,
+                    i + 1
+                );
+            }
+        }
+    }
+}
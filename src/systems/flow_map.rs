@@ -0,0 +1,151 @@
+use crate::components::*;
+use crate::draw;
+use specs::prelude::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// Running away is worth a bit more than being far away, so the flee map
+/// leads past the player out of a dead end rather than into its far corner.
+const FLEE_WEIGHT: f32 = -1.2;
+
+/// Distance maps over the level, built once a round and shared by every
+/// monster. Stepping to the lowest neighbour on `toward` heads for the player,
+/// on `flee` it heads away from them, and on `search` it heads for the nearest
+/// spot a monster last saw whoever it was after.
+#[derive(Default)]
+pub struct FlowMaps {
+    toward: Vec<f32>,
+    flee: Vec<f32>,
+    search: Vec<f32>,
+}
+
+impl FlowMaps {
+    /// Only the terrain counts, the monsters move around too much to bake them in.
+    pub fn build(map: &map::TetraMap, target: (i32, i32), sightings: &[(i32, i32)]) -> FlowMaps {
+        let toward = distances(map, &[target]);
+
+        let mut flee: Vec<f32> = toward
+            .iter()
+            .map(|d| if *d < f32::MAX { d * FLEE_WEIGHT } else { f32::MAX })
+            .collect();
+        let open: Vec<usize> = (0..flee.len()).filter(|i| flee[*i] < f32::MAX).collect();
+        relax(map, &mut flee, open);
+
+        FlowMaps {
+            toward,
+            flee,
+            search: distances(map, sightings),
+        }
+    }
+
+    /// The next step closer to the player from (x, y).
+    pub fn toward(&self, map: &map::TetraMap, x: i32, y: i32) -> Option<(i32, i32)> {
+        downhill(map, &self.toward, x, y)
+    }
+
+    /// The next step further away from the player from (x, y).
+    pub fn away(&self, map: &map::TetraMap, x: i32, y: i32) -> Option<(i32, i32)> {
+        downhill(map, &self.flee, x, y)
+    }
+
+    /// The next step towards the closest last sighting from (x, y).
+    pub fn search(&self, map: &map::TetraMap, x: i32, y: i32) -> Option<(i32, i32)> {
+        downhill(map, &self.search, x, y)
+    }
+}
+
+/// How far every tile is from the nearest of the starts.
+fn distances(map: &map::TetraMap, starts: &[(i32, i32)]) -> Vec<f32> {
+    let mut values = vec![f32::MAX; map.buffer.data.len()];
+    let starts: Vec<usize> = starts.iter().map(|(x, y)| map.xy_idx(*x, *y)).collect();
+    for start in starts.iter() {
+        values[*start] = 0.0;
+    }
+    relax(map, &mut values, starts);
+    values
+}
+
+fn passable(map: &map::TetraMap, x: i32, y: i32) -> bool {
+    x >= 1 && x < map.width() - 1 && y >= 1 && y < map.height() - 1 && *map.buffer.get(x, y) != map::TileType::Wall
+}
+
+/// Spreads the values out from the open tiles until nothing gets any lower,
+/// cheapest tile first so every tile only gets settled the once.
+fn relax(map: &map::TetraMap, values: &mut [f32], open: Vec<usize>) {
+    let mut open: BinaryHeap<Open> = open.into_iter().map(|idx| Open(values[idx], idx)).collect();
+    while let Some(Open(value, idx)) = open.pop() {
+        if value > values[idx] {
+            // got to this one more cheaply since it was queued
+            continue;
+        }
+        for (next, cost) in draw::exits(map, idx, |x, y| passable(map, x, y)) {
+            let value = value + cost;
+            if value < values[next] {
+                values[next] = value;
+                open.push(Open(value, next));
+            }
+        }
+    }
+}
+
+/// A tile waiting in `relax`, ordered so the heap hands out the lowest value first.
+#[derive(PartialEq)]
+struct Open(f32, usize);
+
+impl Eq for Open {}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.total_cmp(&self.0).then(other.1.cmp(&self.1))
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The lowest tile next to (x, y) thats lower than where we are, leaving out
+/// anything somebody is standing on right now.
+fn downhill(map: &map::TetraMap, values: &[f32], x: i32, y: i32) -> Option<(i32, i32)> {
+    // not built for this level yet
+    if values.len() != map.buffer.data.len() {
+        return None;
+    }
+    let here = values[map.xy_idx(x, y)];
+    draw::exits(map, map.xy_idx(x, y), |x, y| passable(map, x, y) && !map.is_blocked(x, y))
+        .iter()
+        .map(|(idx, _)| *idx)
+        .filter(|idx| values[*idx] < here)
+        .min_by(|a, b| values[*a].total_cmp(&values[*b]))
+        .map(|idx| map.xy(idx))
+}
+
+/// Rebuilds the flow maps around the player and wherever the monsters last
+/// saw their targets, at the start of every monster round.
+pub struct FlowMapSystem {}
+
+impl<'a> System<'a> for FlowMapSystem {
+    type SystemData = (
+        ReadExpect<'a, map::TetraMap>,
+        ReadExpect<'a, Entity>,
+        ReadExpect<'a, crate::RunState>,
+        ReadStorage<'a, Position>,
+        ReadStorage<'a, AiState>,
+        WriteExpect<'a, FlowMaps>,
+    );
+
+    fn run(&mut self, (map, player, run_state, positions, ai_states, mut flow): Self::SystemData) {
+        if *run_state != crate::RunState::MonsterTurn {
+            return;
+        }
+        // sorted, the same sightings have to make the same map every time
+        let mut sightings: Vec<(i32, i32)> = ai_states.join().filter_map(|ai| ai.last_seen).collect();
+        sightings.sort();
+        sightings.dedup();
+        if let Some(pos) = positions.get(*player) {
+            *flow = FlowMaps::build(&map, (pos.x, pos.y), &sightings);
+        }
+    }
+}
@@ -1,6 +1,33 @@
 use crate::components::*;
+use crate::draw;
+use crate::raws::{RawMaster, Reaction};
+use crate::util::{dice, Rng, RngResource};
+use log::debug;
+use std::collections::HashSet;
 
+use crate::saveload::SerializeMe;
 use specs::prelude::*;
+use specs::saveload::{Marker, SimpleMarker};
+
+mod flow_map;
+pub use flow_map::{FlowMapSystem, FlowMaps};
+
+/// Joined entities put in the order they were made in. Join order goes by
+/// entity id and those get handed out again when a save is loaded, the save
+/// markers dont, so anything where going first matters goes through here.
+pub fn in_creation_order<T, I, F>(
+    markers: &ReadStorage<SimpleMarker<SerializeMe>>,
+    joined: I,
+    entity: F,
+) -> Vec<T>
+where
+    I: Iterator<Item = T>,
+    F: Fn(&T) -> Entity,
+{
+    let mut joined: Vec<T> = joined.collect();
+    joined.sort_by_key(|item| markers.get(entity(item)).map(|marker| marker.id()));
+    joined
+}
 
 pub struct VisibilitySystem {}
 impl<'a> System<'a> for VisibilitySystem {
@@ -28,11 +55,35 @@ impl<'a> System<'a> for VisibilitySystem {
     }
 }
 
-pub struct MonsterAi {}
+/// Hands out every round's worth of energy, whoever saved up enough gets
+/// to act.
+pub struct InitiativeSystem {}
+
+impl<'a> System<'a> for InitiativeSystem {
+    type SystemData = (
+        ReadExpect<'a, crate::RunState>,
+        WriteStorage<'a, Energy>,
+        ReadStorage<'a, StatusEffects>,
+    );
+
+    fn run(&mut self, (run_state, mut energies, statuses): Self::SystemData) {
+        if *run_state != crate::RunState::MonsterTurn {
+            return;
+        }
+        for (energy, status) in (&mut energies, statuses.maybe()).join() {
+            energy.energy += energy.gain(status);
+        }
+    }
+}
+
+/// Every monster with the energy for it takes one action, `acted` says if any did.
+pub struct MonsterAi {
+    pub acted: bool,
+}
 
 impl<'a> System<'a> for MonsterAi {
     type SystemData = (
-        WriteExpect<'a, map::TetraMap>,
+        ReadExpect<'a, map::TetraMap>,
         ReadExpect<'a, Entity>,
         ReadExpect<'a, crate::RunState>,
         Entities<'a>,
@@ -40,12 +91,26 @@ impl<'a> System<'a> for MonsterAi {
         ReadStorage<'a, Monster>,
         WriteStorage<'a, Position>,
         WriteStorage<'a, WantsToMelee>,
+        ReadStorage<'a, StatusEffects>,
+        WriteExpect<'a, RngResource>,
+        WriteStorage<'a, Energy>,
+        ReadStorage<'a, CombatStats>,
+        ReadStorage<'a, Temperament>,
+        WriteStorage<'a, AiState>,
+        ReadExpect<'a, FlowMaps>,
+        WriteStorage<'a, RangedAttack>,
+        ReadStorage<'a, Ranged>,
+        WriteStorage<'a, WantsToUseItem>,
+        ReadStorage<'a, Faction>,
+        ReadExpect<'a, RawMaster>,
+        WriteStorage<'a, WantsToOpenDoor>,
+        ReadStorage<'a, SimpleMarker<SerializeMe>>,
     );
 
     fn run(
         &mut self,
         (
-            mut map,
+            map,
             player_entity,
             run_state,
             entities,
@@ -53,53 +118,445 @@ impl<'a> System<'a> for MonsterAi {
             monster,
             mut positions,
             mut wants_to_melee,
+            statuses,
+            mut rng,
+            mut energies,
+            combat_stats,
+            temperaments,
+            mut ai_states,
+            flow,
+            mut ranged_attacks,
+            ranged,
+            mut wants_to_use,
+            factions,
+            raws,
+            mut wants_to_open,
+            markers,
         ): Self::SystemData,
     ) {
-        // TODO get the RNG state out of here
-        use rltk::a_star_search;
         if *run_state != crate::RunState::MonsterTurn {
             return;
         }
 
         let player_entity = *player_entity;
-        let Position { x: px, y: py } = *positions
-            .get(player_entity)
-            .expect("Player is expected to be positional");
+        let mut moves = Moves::default();
 
-        for (ent, viewshed, _monster, pos) in
-            (&entities, &mut viewshed, &monster, &mut positions).join()
+        // everybody who takes a side, where they are standing and who they are with
+        let mut creatures: Vec<(Entity, (i32, i32), &str)> = in_creation_order(
+            &markers,
+            (&entities, &positions, &factions, &combat_stats)
+                .join()
+                .map(|(ent, pos, faction, _)| {
+                    (ent, (pos.x, pos.y), faction.allegiance(statuses.get(ent)))
+                }),
+            |creature| creature.0,
+        );
+
+        let actors = (
+            &entities,
+            &mut viewshed,
+            &monster,
+            &mut positions,
+            &mut energies,
+            &combat_stats,
+            &temperaments,
+            &mut ai_states,
+            &factions,
+            (&mut ranged_attacks).maybe(),
+        )
+            .join();
+        for (ent, viewshed, _monster, pos, energy, stats, temperament, ai, faction, mut shooter) in
+            in_creation_order(&markers, actors, |actor| actor.0)
         {
-            let idx = map.nav_buffer.xy_idx(px, py);
-
-            if viewshed.visible_tiles.contains(&idx) {
-                let path = a_star_search(
-                    // map.nav_buffer.xy_idx(pos.x, pos.y),
-                    map.nav_buffer.xy_idx(pos.x, pos.y),
-                    map.nav_buffer.xy_idx(px, py),
-                    // *target,
-                    &mut *map,
-                );
-                if path.success && path.steps.len() > 2 {
-                    pos.x = path.steps[1] as i32 % map.width();
-                    pos.y = path.steps[1] as i32 / map.width();
-                    viewshed.dirty = true;
+            let status = statuses.get(ent);
+            let has = |kind| status.is_some_and(|s| s.has(kind));
+            let side = faction.allegiance(status);
+
+            // one action a pass, fast monsters get their next one in the pass after
+            if !energy.ready() {
+                continue;
+            }
+            self.acted = true;
+            energy.energy -= ACTION_COST;
+            if let Some(shooter) = shooter.as_mut() {
+                shooter.recharge = i32::max(0, shooter.recharge - 1);
+            }
+            if has(StatusKind::Sleep) {
+                continue;
+            }
+            if has(StatusKind::Confusion) {
+                let x = pos.x + rng.between(-1, 2);
+                let y = pos.y + rng.between(-1, 2);
+                let in_bounds = x >= 0 && x < map.width() && y >= 0 && y < map.height();
+                if in_bounds && !map.is_blocked(x, y) {
+                    moves.step(&map, ent, pos, viewshed, (x, y));
                 }
+                moved(&mut creatures, ent, pos);
+                continue;
             }
 
-            let distance = rltk::DistanceAlg::Pythagoras
-                .distance2d(rltk::Point::new(pos.x, pos.y), rltk::Point::new(px, py));
+            let (target, threat) = look_around(&raws, &map, viewshed, ent, side, pos, &creatures);
+            if let Some(target) = target {
+                ai.last_seen = Some(target.at);
+            }
+            ai.mode = next_mode(ai, target.is_some(), threat.is_some(), stats, temperament);
 
-            if distance < 1.5 {
+            let mut attack = None;
+            match ai.mode {
+                AiMode::Wander => {
+                    let player_at = creatures
+                        .iter()
+                        .find(|(other, _, _)| *other == player_entity)
+                        .map(|(_, at, _)| *at);
+                    // allies tag along after the player when theres nobody to fight
+                    let straying = player_at.is_some_and(|at| distance((pos.x, pos.y), at) > 2.5);
+                    if side == PLAYER_FACTION && straying {
+                        if let Some(next) = flow.toward(&map, pos.x, pos.y) {
+                            moves.step(&map, ent, pos, viewshed, next);
+                        }
+                    } else if temperament.wanders && rng.between(0, 2) == 0 {
+                        let (x, y) = (pos.x + rng.between(-1, 2), pos.y + rng.between(-1, 2));
+                        // wandering about doesnt go through doors
+                        if *map.buffer.get(x, y) != map::TileType::DoorClosed
+                            && !map.is_blocked(x, y)
+                        {
+                            moves.step(&map, ent, pos, viewshed, (x, y));
+                        }
+                    }
+                }
+                AiMode::Chase => {
+                    let target = target.expect("Chasing without anybody to chase");
+                    let range = shooter
+                        .as_ref()
+                        .and_then(|s| ranged.get(s.ability))
+                        .map_or(0, |r| r.range);
+                    let keep_distance = shooter.as_ref().map_or(0, |s| s.keep_distance);
+                    let retreat = if target.distance < keep_distance as f32 {
+                        away(&map, &flow, pos, player_entity, &target)
+                    } else {
+                        None
+                    };
+
+                    if let Some(shooter) = shooter.as_mut().filter(|s| {
+                        s.recharge == 0
+                            && target.distance <= range as f32
+                            && clear_shot(&map, pos, target.at)
+                    }) {
+                        wants_to_use
+                            .insert(
+                                ent,
+                                WantsToUseItem {
+                                    item: shooter.ability,
+                                    target: Some(target.at),
+                                },
+                            )
+                            .expect("Unable to insert intent");
+                        shooter.recharge = shooter.cooldown;
+                    } else if let Some(next) = retreat {
+                        moves.step(&map, ent, pos, viewshed, next);
+                    } else if target.distance < 1.5 {
+                        attack = Some(target.entity);
+                    } else if target.distance > keep_distance as f32 {
+                        if let Some(next) = toward(&map, &flow, pos, player_entity, &target) {
+                            moves.step(&map, ent, pos, viewshed, next);
+                        }
+                    }
+                }
+                AiMode::Search { .. } => {
+                    let target = ai.last_seen.expect("Searching without anywhere to look");
+                    // stuck somewhere along the way, it keeps at it until it runs out of turns
+                    if let Some(next) = flow.search(&map, pos.x, pos.y) {
+                        moves.step(&map, ent, pos, viewshed, next);
+                    }
+                    if (pos.x, pos.y) == target {
+                        // nobody here, back to whatever it was doing before
+                        ai.last_seen = None;
+                    }
+                }
+                AiMode::Flee => {
+                    // whatever scared it, otherwise whoever hurt it
+                    let danger = threat.or(target).expect("Fleeing from nobody");
+                    match away(&map, &flow, pos, player_entity, &danger) {
+                        Some(next) => moves.step(&map, ent, pos, viewshed, next),
+                        // backed into a corner, it might as well fight
+                        None if danger.distance < 1.5 => attack = Some(danger.entity),
+                        None => {}
+                    }
+                }
+            }
+            moved(&mut creatures, ent, pos);
+
+            if let Some(target) = attack {
                 wants_to_melee
-                    .insert(
-                        ent,
-                        WantsToMelee {
-                            target: player_entity,
-                        },
-                    )
+                    .insert(ent, WantsToMelee { target })
                     .expect("Unable to insert attack");
             }
         }
+
+        for (ent, (x, y)) in moves.doors {
+            wants_to_open
+                .insert(ent, WantsToOpenDoor { x, y })
+                .expect("Unable to insert intent");
+        }
+    }
+}
+
+/// Somebody a monster has its eye on.
+#[derive(Clone, Copy)]
+struct Sighting {
+    entity: Entity,
+    at: (i32, i32),
+    distance: f32,
+}
+
+fn distance(from: (i32, i32), to: (i32, i32)) -> f32 {
+    rltk::DistanceAlg::Pythagoras.distance2d(
+        rltk::Point::new(from.0, from.1),
+        rltk::Point::new(to.0, to.1),
+    )
+}
+
+/// The closest one in sight it wants to attack, and the closest one it wants
+/// to get away from.
+fn look_around(
+    raws: &RawMaster,
+    map: &map::TetraMap,
+    viewshed: &Viewshed,
+    me: Entity,
+    side: &str,
+    pos: &Position,
+    creatures: &[(Entity, (i32, i32), &str)],
+) -> (Option<Sighting>, Option<Sighting>) {
+    let mut target: Option<Sighting> = None;
+    let mut threat: Option<Sighting> = None;
+    for (other, at, theirs) in creatures.iter() {
+        if *other == me || !viewshed.visible_tiles.contains(&map.xy_idx(at.0, at.1)) {
+            continue;
+        }
+        let closest = match raws.reaction(side, theirs) {
+            Reaction::Attack => &mut target,
+            Reaction::Flee => &mut threat,
+            Reaction::Ignore => continue,
+        };
+        let distance = distance((pos.x, pos.y), *at);
+        if closest.as_ref().is_none_or(|c| distance < c.distance) {
+            *closest = Some(Sighting {
+                entity: *other,
+                at: *at,
+                distance,
+            });
+        }
+    }
+    (target, threat)
+}
+
+/// Keeps the list of who is where up to date after a monster has had its go.
+fn moved(creatures: &mut [(Entity, (i32, i32), &str)], ent: Entity, pos: &Position) {
+    if let Some((_, at, _)) = creatures.iter_mut().find(|(other, _, _)| *other == ent) {
+        *at = (pos.x, pos.y);
+    }
+}
+
+/// The next step closer to whoever it is, the flow maps already know the way
+/// to the player.
+fn toward(
+    map: &map::TetraMap,
+    flow: &FlowMaps,
+    pos: &Position,
+    player: Entity,
+    target: &Sighting,
+) -> Option<(i32, i32)> {
+    if target.entity == player {
+        return flow.toward(map, pos.x, pos.y);
+    }
+    greedy_step(map, pos, |at| distance(at, target.at))
+}
+
+/// The next step further away from whoever it is.
+fn away(
+    map: &map::TetraMap,
+    flow: &FlowMaps,
+    pos: &Position,
+    player: Entity,
+    danger: &Sighting,
+) -> Option<(i32, i32)> {
+    if danger.entity == player {
+        return flow.away(map, pos.x, pos.y);
+    }
+    greedy_step(map, pos, |at| -distance(at, danger.at))
+}
+
+/// Theres only a flow map for the player, for anybody else its the open tile
+/// next to it that scores lowest, as long as thats better than staying put.
+/// They are in sight, so going straight for them (or away) mostly works out.
+fn greedy_step<F: Fn((i32, i32)) -> f32>(
+    map: &map::TetraMap,
+    pos: &Position,
+    score: F,
+) -> Option<(i32, i32)> {
+    let here = score((pos.x, pos.y));
+    draw::exits(map, map.xy_idx(pos.x, pos.y), |x, y| {
+        x >= 1 && x < map.width() - 1 && y >= 1 && y < map.height() - 1 && !map.is_blocked(x, y)
+    })
+    .iter()
+    .map(|(idx, _)| map.xy(*idx))
+    .map(|at| (at, score(at)))
+    .filter(|(_, score)| *score < here)
+    .min_by(|a, b| a.1.total_cmp(&b.1))
+    .map(|(at, _)| at)
+}
+
+/// Works out what a monster should be doing now, from what it was doing and
+/// what it can see.
+fn next_mode(
+    ai: &AiState,
+    sees_target: bool,
+    scared: bool,
+    stats: &CombatStats,
+    temperament: &Temperament,
+) -> AiMode {
+    if scared {
+        return AiMode::Flee;
+    }
+    let hurt = stats.hp * 100 < temperament.flee_below * stats.max_hp;
+    if hurt {
+        // out of sight is good enough for it, it wont go looking for more
+        return if sees_target {
+            AiMode::Flee
+        } else {
+            AiMode::Wander
+        };
+    }
+    if sees_target {
+        return AiMode::Chase;
+    }
+    match ai.mode {
+        _ if ai.last_seen.is_none() => AiMode::Wander,
+        AiMode::Chase if temperament.search_turns > 0 => AiMode::Search {
+            turns: temperament.search_turns,
+        },
+        AiMode::Search { turns } if turns > 1 => AiMode::Search { turns: turns - 1 },
+        _ => AiMode::Wander,
+    }
+}
+
+/// Nothing between the two that would stop a shot, be it a wall, a closed
+/// door or somebody standing in the way.
+fn clear_shot(map: &map::TetraMap, from: &Position, to: (i32, i32)) -> bool {
+    rltk::line2d(
+        rltk::LineAlg::Bresenham,
+        rltk::Point::new(from.x, from.y),
+        rltk::Point::new(to.0, to.1),
+    )
+    .iter()
+    .filter(|p| (p.x, p.y) != (from.x, from.y) && (p.x, p.y) != to)
+    .all(|p| !map.buffer.get(p.x, p.y).is_opaque() && !map.is_blocked(p.x, p.y))
+}
+
+/// Where the monsters are headed this pass. The map stays as it is while they
+/// decide, so the tiles they move into and the doors they want opened are
+/// kept here.
+#[derive(Default)]
+struct Moves {
+    taken: HashSet<usize>,
+    doors: Vec<(Entity, (i32, i32))>,
+}
+
+impl Moves {
+    /// Moves the monster onto the tile, or asks for the door thats there to be
+    /// opened instead. It stays put if somebody got to the tile first.
+    fn step(
+        &mut self,
+        map: &map::TetraMap,
+        ent: Entity,
+        pos: &mut Position,
+        viewshed: &mut Viewshed,
+        (x, y): (i32, i32),
+    ) {
+        if *map.buffer.get(x, y) == map::TileType::DoorClosed {
+            self.doors.push((ent, (x, y)));
+            return;
+        }
+        if !self.taken.insert(map.xy_idx(x, y)) {
+            return;
+        }
+        pos.x = x;
+        pos.y = y;
+        viewshed.dirty = true;
+    }
+}
+
+/// Opens the doors the monsters walked into.
+pub struct DoorSystem {}
+
+impl<'a> System<'a> for DoorSystem {
+    type SystemData = (
+        WriteExpect<'a, map::TetraMap>,
+        WriteStorage<'a, WantsToOpenDoor>,
+        WriteStorage<'a, Viewshed>,
+    );
+
+    fn run(&mut self, (mut map, mut doors, mut viewsheds): Self::SystemData) {
+        let mut opened = false;
+        for door in doors.join() {
+            opened |= map.open_door(door.x, door.y);
+        }
+        doors.clear();
+        // everybody might see a bit more now
+        if opened {
+            for viewshed in (&mut viewsheds).join() {
+                viewshed.dirty = true;
+            }
+        }
+    }
+}
+
+/// Ticks every status effect down once a round, and does whatever they do
+/// each round while they last.
+pub struct StatusEffectSystem {}
+
+impl<'a> System<'a> for StatusEffectSystem {
+    type SystemData = (
+        ReadExpect<'a, Entity>,
+        ReadExpect<'a, crate::RunState>,
+        WriteExpect<'a, GameLog>,
+        Entities<'a>,
+        WriteStorage<'a, StatusEffects>,
+        WriteStorage<'a, SufferDamage>,
+        ReadStorage<'a, Name>,
+        ReadStorage<'a, SimpleMarker<SerializeMe>>,
+    );
+
+    fn run(
+        &mut self,
+        (player, run_state, mut gamelog, entities, mut statuses, mut suffer_damage, names, markers): Self::SystemData,
+    ) {
+        // once a round, same as the monsters
+        if *run_state != crate::RunState::MonsterTurn {
+            return;
+        }
+
+        for (entity, status) in
+            in_creation_order(&markers, (&entities, &mut statuses).join(), |e| e.0)
+        {
+            for effect in status.effects.iter_mut() {
+                if effect.kind == StatusKind::Poison {
+                    SufferDamage::new_damage(&mut suffer_damage, entity, POISON_DAMAGE, "poison");
+                    if entity == *player {
+                        gamelog.say(format!("The poison burns you for {} hp.", POISON_DAMAGE));
+                    }
+                }
+                effect.turns -= 1;
+                if effect.turns <= 0 {
+                    if entity == *player {
+                        gamelog.say(format!("You are no longer {}.", effect.kind.describe()));
+                    } else if let Some(name) = names.get(entity) {
+                        debug!("{} is no longer {}", name.name, effect.kind.describe());
+                    }
+                }
+            }
+            status.effects.retain(|e| e.turns > 0);
+        }
     }
 }
 
@@ -115,12 +572,13 @@ impl<'a> System<'a> for MapIndexingSystem {
         ReadStorage<'a, Position>,
         ReadStorage<'a, BlocksTile>,
         Entities<'a>,
+        ReadStorage<'a, SimpleMarker<SerializeMe>>,
     );
 
-    fn run(&mut self, (mut map, pos, tile, ent): Self::SystemData) {
+    fn run(&mut self, (mut map, pos, tile, ent, markers): Self::SystemData) {
         map.gen_nav_buffer();
         map.clear_entities();
-        for (pos, ent) in (&pos, &ent).join() {
+        for (pos, ent) in in_creation_order(&markers, (&pos, &ent).join(), |e| e.1) {
             if tile.contains(ent) {
                 map.nav_buffer.set(pos.x, pos.y, true);
             }
@@ -129,6 +587,8 @@ impl<'a> System<'a> for MapIndexingSystem {
     }
 }
 
+/// Swings get a d20 attack roll against the target's armor. A natural 20 always
+/// hits and rolls the damage dice twice, a natural 1 always misses.
 pub struct MeleeCombatSystem {}
 
 impl<'a> System<'a> for MeleeCombatSystem {
@@ -139,31 +599,84 @@ impl<'a> System<'a> for MeleeCombatSystem {
         ReadStorage<'a, Name>,
         ReadStorage<'a, CombatStats>,
         WriteStorage<'a, SufferDamage>,
+        ReadStorage<'a, Equipped>,
+        ReadStorage<'a, MeleePowerBonus>,
+        ReadStorage<'a, DefenseBonus>,
+        ReadStorage<'a, MeleeDamage>,
+        WriteExpect<'a, RngResource>,
+        ReadStorage<'a, SimpleMarker<SerializeMe>>,
     );
     fn run(
         &mut self,
-        (entities, mut game_log, mut want_melee, names, combat_stats, mut suffer_damage): Self::SystemData,
+        (
+            entities,
+            mut game_log,
+            mut want_melee,
+            names,
+            combat_stats,
+            mut suffer_damage,
+            equipped,
+            power_bonuses,
+            defense_bonuses,
+            weapons,
+            mut rng,
+            markers,
+        ): Self::SystemData,
     ) {
-        for (_ent, want_melee, name, stats) in
-            (&entities, &want_melee, &names, &combat_stats).join()
-        {
+        let swings = (&entities, &want_melee, &names, &combat_stats).join();
+        for (ent, want_melee, name, stats) in in_creation_order(&markers, swings, |swing| swing.0) {
             let target_stats = combat_stats.get(want_melee.target).unwrap();
             if target_stats.hp > 0 {
                 let target_name = names.get(want_melee.target).unwrap();
-                let damage = i32::max(0, stats.power - target_stats.defense);
 
-                if damage == 0 {
-                    game_log.say(format!(
-                        "{} is unable to hurt {}",
-                        name.name, target_name.name
-                    ));
-                } else {
-                    game_log.say(format!(
-                        "{} hits {}, for {} hp",
-                        name.name, target_name.name, damage
-                    ));
-                    SufferDamage::new_damage(&mut suffer_damage, want_melee.target, damage);
-                }
+                // whatever either side is wearing counts on top of their own stats
+                let power_bonus: i32 = (&equipped, &power_bonuses)
+                    .join()
+                    .filter(|(worn, _)| worn.owner == ent)
+                    .map(|(_, bonus)| bonus.power)
+                    .sum();
+                let defense_bonus: i32 = (&equipped, &defense_bonuses)
+                    .join()
+                    .filter(|(worn, _)| worn.owner == want_melee.target)
+                    .map(|(_, bonus)| bonus.defense)
+                    .sum();
+                let dice = (&equipped, &weapons)
+                    .join()
+                    .find(|(worn, _)| worn.owner == ent)
+                    .map_or(stats.damage, |(_, weapon)| weapon.damage);
+
+                let armor = BASE_ARMOR + target_stats.defense + defense_bonus;
+                let msg = match dice::attack(&mut *rng, stats.power + power_bonus, armor, dice) {
+                    dice::Attack::Fumble => {
+                        format!("{} fumbles the attack on {}", name.name, target_name.name)
+                    }
+                    dice::Attack::Miss => format!("{} misses {}", name.name, target_name.name),
+                    dice::Attack::Hit(0) | dice::Attack::Critical(0) => {
+                        format!("{} is unable to hurt {}", name.name, target_name.name)
+                    }
+                    dice::Attack::Hit(damage) => {
+                        SufferDamage::new_damage(
+                            &mut suffer_damage,
+                            want_melee.target,
+                            damage,
+                            &name.name,
+                        );
+                        format!("{} hits {}, for {} hp", name.name, target_name.name, damage)
+                    }
+                    dice::Attack::Critical(damage) => {
+                        SufferDamage::new_damage(
+                            &mut suffer_damage,
+                            want_melee.target,
+                            damage,
+                            &name.name,
+                        );
+                        format!(
+                            "{} critically hits {}, for {} hp!",
+                            name.name, target_name.name, damage
+                        )
+                    }
+                };
+                game_log.say(msg);
             }
         }
 
@@ -174,13 +687,33 @@ impl<'a> System<'a> for MeleeCombatSystem {
 pub struct DamageSystem {}
 impl<'a> System<'a> for DamageSystem {
     type SystemData = (
+        ReadExpect<'a, Entity>,
+        WriteExpect<'a, KilledBy>,
+        Entities<'a>,
         WriteStorage<'a, CombatStats>,
         WriteStorage<'a, SufferDamage>,
+        WriteStorage<'a, StatusEffects>,
     );
 
-    fn run(&mut self, (mut stats, mut damage): Self::SystemData) {
-        for (mut stats, damage) in (&mut stats, &damage).join() {
-            stats.hp -= damage.amount.iter().sum::<i32>();
+    fn run(
+        &mut self,
+        (player, mut killed_by, entities, mut stats, mut damage, mut statuses): Self::SystemData,
+    ) {
+        for (entity, stats, damage, status) in
+            (&entities, &mut stats, &damage, (&mut statuses).maybe()).join()
+        {
+            let amount = damage.amount.iter().map(|(amount, _)| amount).sum::<i32>();
+            let was_alive = stats.hp > 0;
+            stats.hp -= amount;
+            if entity == *player && was_alive && stats.hp < 1 {
+                killed_by.0 = damage.amount.last().map(|(_, source)| source.clone());
+            }
+            // getting hurt wakes you right up
+            if let Some(status) = status {
+                if amount > 0 {
+                    status.effects.retain(|e| e.kind != StatusKind::Sleep);
+                }
+            }
         }
         damage.clear();
     }
@@ -222,7 +755,6 @@ impl<'a> System<'a> for ItemCollectionSystem {
     }
 }
 
-
 pub struct ItemUseSystem {}
 
 impl<'a> System<'a> for ItemUseSystem {
@@ -237,43 +769,204 @@ impl<'a> System<'a> for ItemUseSystem {
         ReadStorage<'a, InflictsDamage>,
         WriteStorage<'a, SufferDamage>,
         ReadStorage<'a, Consumable>,
-        WriteStorage<'a, CombatStats>
+        WriteStorage<'a, CombatStats>,
+        ReadStorage<'a, Equippable>,
+        WriteStorage<'a, Equipped>,
+        WriteStorage<'a, InBackpack>,
+        ReadStorage<'a, AreaOfEffect>,
+        ReadStorage<'a, GrantsStatus>,
+        WriteStorage<'a, StatusEffects>,
+        ReadStorage<'a, Item>,
+        ReadStorage<'a, SimpleMarker<SerializeMe>>,
     );
-    fn run(&mut self, (player, map, mut gamelog, entities, use_intents, names, potions, inflict_damage, mut suffer_damage, consumables, mut combat_stats): Self::SystemData) {
-        for(entity, intent, stats) in (&entities, &use_intents, &mut combat_stats).join() {
+    fn run(
+        &mut self,
+        (
+            player,
+            map,
+            mut gamelog,
+            entities,
+            mut use_intents,
+            names,
+            potions,
+            inflict_damage,
+            mut suffer_damage,
+            consumables,
+            mut combat_stats,
+            equippables,
+            mut equipped,
+            mut backpacks,
+            aoe,
+            grants_status,
+            mut statuses,
+            items,
+            markers,
+        ): Self::SystemData,
+    ) {
+        let uses = (&entities, &use_intents, &mut combat_stats).join();
+        for (entity, intent, stats) in in_creation_order(&markers, uses, |u| u.0) {
             let mut use_item = false;
             if let Some(potion) = potions.get(intent.item) {
                 stats.hp = i32::min(stats.max_hp, stats.hp + potion.heal_amount);
                 if entity == *player {
-                    gamelog.entries.push(format!("You drink the {}, healing {} hp", names.get(intent.item).unwrap().name, potion.heal_amount));
+                    gamelog.entries.push(format!(
+                        "You drink the {}, healing {} hp",
+                        names.get(intent.item).unwrap().name,
+                        potion.heal_amount
+                    ));
                     use_item = true;
                 }
             }
 
+            // aimed items hit whoever is where they land, the rest go on the user
+            let targets: Vec<Entity> = match intent.target {
+                Some((x, y)) => {
+                    let tiles = match aoe.get(intent.item) {
+                        Some(area) => {
+                            // it goes off whether or not theres anybody there to catch it
+                            use_item = entity == *player;
+                            map.blast_area(x, y, area.radius)
+                        }
+                        None => vec![(x, y)],
+                    };
+                    tiles
+                        .iter()
+                        .flat_map(|(x, y)| map.entities.get(*x, *y).iter().copied())
+                        .filter(|mob| !items.contains(*mob))
+                        .collect()
+                }
+                None => vec![entity],
+            };
+
             if let Some(damage) = inflict_damage.get(intent.item) {
-                let target_point = intent.target.unwrap();
-                for mob in map.entities.get(target_point.0, target_point.1) {
-                    SufferDamage::new_damage(&mut suffer_damage, *mob, damage.damage);
+                let item_name = &names.get(intent.item).unwrap().name;
+                // monsters dont carry items around, theirs are shots and spells
+                let source = match names.get(entity) {
+                    Some(user) if entity != *player => format!("{}'s {}", user.name, item_name),
+                    _ => item_name.clone(),
+                };
+                for mob in targets.iter() {
+                    SufferDamage::new_damage(&mut suffer_damage, *mob, damage.damage, &source);
+                    let mob_name = &names.get(*mob).unwrap().name;
                     if entity == *player {
-                        let mob_name = names.get(*mob).unwrap();
-                        let item_name = names.get(intent.item).unwrap();
-                        gamelog.entries.push(format!("You use {} on {}, inflicting {} hp.", item_name.name, mob_name.name, damage.damage));
+                        gamelog.entries.push(format!(
+                            "You use {} on {}, inflicting {} hp.",
+                            item_name, mob_name, damage.damage
+                        ));
                         use_item = true;
+                    } else if let Some(user) = names.get(entity) {
+                        gamelog.say(format!(
+                            "{} uses {} on {}, inflicting {} hp.",
+                            user.name, item_name, mob_name, damage.damage
+                        ));
                     }
                 }
             }
 
-            if use_item {
-                if consumables.contains(intent.item) {
-                    entities.delete(intent.item).expect("Couldn't delete the item after use");
+            if let Some(grants) = grants_status.get(intent.item) {
+                for mob in targets.iter() {
+                    StatusEffects::apply(&mut statuses, *mob, grants.kind, grants.turns);
+                    if *mob == *player {
+                        gamelog.say(format!("You are {}.", grants.kind.describe()));
+                    } else if entity == *player {
+                        gamelog.say(format!(
+                            "{} is {}.",
+                            names.get(*mob).unwrap().name,
+                            grants.kind.describe()
+                        ));
+                    }
+                    use_item = use_item || entity == *player;
                 }
             }
+
+            if let Some(equippable) = equippables.get(intent.item) {
+                let slot = equippable.slot;
+
+                // whatever is already in that slot goes back into the backpack
+                let worn: Vec<Entity> = (&entities, &equipped)
+                    .join()
+                    .filter(|(_, worn)| worn.owner == entity && worn.slot == slot)
+                    .map(|(item, _)| item)
+                    .collect();
+                for item in worn {
+                    equipped.remove(item);
+                    backpacks
+                        .insert(item, InBackpack { owner: entity })
+                        .expect("Unable to insert into the backpack");
+                    if entity == *player {
+                        gamelog.say(format!(
+                            "You unequip the {}.",
+                            names.get(item).unwrap().name
+                        ));
+                    }
+                }
+
+                backpacks.remove(intent.item);
+                equipped
+                    .insert(
+                        intent.item,
+                        Equipped {
+                            owner: entity,
+                            slot,
+                        },
+                    )
+                    .expect("Unable to equip the item");
+                if entity == *player {
+                    gamelog.say(format!(
+                        "You equip the {}.",
+                        names.get(intent.item).unwrap().name
+                    ));
+                }
+            }
+
+            if use_item && consumables.contains(intent.item) {
+                entities
+                    .delete(intent.item)
+                    .expect("Couldn't delete the item after use");
+            }
+        }
+
+        use_intents.clear();
+    }
+}
+
+/// Takes equipped items off and puts them back in the backpack.
+pub struct ItemRemoveSystem {}
+
+impl<'a> System<'a> for ItemRemoveSystem {
+    type SystemData = (
+        ReadExpect<'a, Entity>,
+        WriteExpect<'a, GameLog>,
+        Entities<'a>,
+        WriteStorage<'a, WantsToRemoveItem>,
+        ReadStorage<'a, Name>,
+        WriteStorage<'a, Equipped>,
+        WriteStorage<'a, InBackpack>,
+    );
+
+    fn run(
+        &mut self,
+        (player, mut gamelog, entities, mut removals, names, mut equipped, mut backpacks): Self::SystemData,
+    ) {
+        for (entity, removal) in (&entities, &removals).join() {
+            equipped.remove(removal.item);
+            backpacks
+                .insert(removal.item, InBackpack { owner: entity })
+                .expect("Unable to insert into the backpack");
+            if entity == *player {
+                gamelog.say(format!(
+                    "You unequip the {}.",
+                    names.get(removal.item).unwrap().name
+                ));
+            }
         }
+
+        removals.clear();
     }
 }
 
 pub struct LootSystem {}
-impl <'a> System<'a> for LootSystem {
+impl<'a> System<'a> for LootSystem {
     type SystemData = (
         ReadExpect<'a, Entity>,
         WriteExpect<'a, GameLog>,
@@ -281,19 +974,30 @@ impl <'a> System<'a> for LootSystem {
         WriteStorage<'a, WantsToDropItem>,
         ReadStorage<'a, Name>,
         WriteStorage<'a, Position>,
-        WriteStorage<'a, InBackpack>
+        WriteStorage<'a, InBackpack>,
     );
 
-    fn run(&mut self, (player, mut gamelog, entities, mut drops, names, mut positions, mut backpacks): Self::SystemData) {
+    fn run(
+        &mut self,
+        (player, mut gamelog, entities, mut drops, names, mut positions, mut backpacks): Self::SystemData,
+    ) {
         for (entity, drop) in (&entities, &drops).join() {
-            let dropper_pos = positions.get(entity).get_or_insert(&Position{x: 0, y:0}).clone();
-            positions.insert(drop.item,dropper_pos).expect("Unable to inser position");
+            let dropper_pos = positions
+                .get(entity)
+                .get_or_insert(&Position { x: 0, y: 0 })
+                .clone();
+            positions
+                .insert(drop.item, dropper_pos)
+                .expect("Unable to inser position");
             backpacks.remove(drop.item);
             if entity == *player {
-                gamelog.say(format!("You drop the {}", names.get(drop.item).unwrap().name));
+                gamelog.say(format!(
+                    "You drop the {}",
+                    names.get(drop.item).unwrap().name
+                ));
             }
         }
 
         drops.clear();
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,161 @@
+use super::Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Dice in the usual notation, `1d6+2` is one six sided die plus two.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Dice {
+    pub count: i32,
+    pub sides: i32,
+    pub bonus: i32,
+}
+
+impl Dice {
+    pub fn new(count: i32, sides: i32, bonus: i32) -> Dice {
+        Dice { count, sides, bonus }
+    }
+
+    /// Reads `NdS`, `NdS+B` or `NdS-B`, the count can be left out for a single die.
+    pub fn parse(text: &str) -> Result<Dice, String> {
+        let bad = || format!("\"{}\" isnt dice, it should look like \"1d6+2\"", text);
+        let (count, rest) = text.trim().split_once('d').ok_or_else(bad)?;
+        let (sides, bonus) = match rest.find(['+', '-']) {
+            Some(i) => rest.split_at(i),
+            None => (rest, "0"),
+        };
+        let count = if count.is_empty() { Ok(1) } else { count.parse() };
+        let dice = match (count, sides.parse(), bonus.parse()) {
+            (Ok(count), Ok(sides), Ok(bonus)) => Dice::new(count, sides, bonus),
+            _ => return Err(bad()),
+        };
+        if dice.count < 1 || dice.sides < 1 {
+            return Err(format!("\"{}\" needs at least one die with at least one side", text));
+        }
+        Ok(dice)
+    }
+
+    pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> i32 {
+        (0..self.count).map(|_| rng.between(1, self.sides + 1)).sum::<i32>() + self.bonus
+    }
+
+    /// Twice as many dice but the bonus only the once, for critical hits.
+    pub fn doubled(self) -> Dice {
+        Dice {
+            count: self.count * 2,
+            ..self
+        }
+    }
+
+    /// The same dice with a bit more (or less) on top.
+    pub fn plus(self, bonus: i32) -> Dice {
+        Dice {
+            bonus: self.bonus + bonus,
+            ..self
+        }
+    }
+}
+
+/// How a swing went.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Attack {
+    /// A natural 1, misses whatever the numbers say.
+    Fumble,
+    Miss,
+    Hit(i32),
+    /// A natural 20, hits whatever the numbers say and rolls the dice twice over.
+    Critical(i32),
+}
+
+/// Rolls a d20 plus `to_hit` against `armor`, and the damage when it lands.
+pub fn attack<R: Rng + ?Sized>(rng: &mut R, to_hit: i32, armor: i32, damage: Dice) -> Attack {
+    match rng.between(1, 21) {
+        1 => Attack::Fumble,
+        20 => Attack::Critical(i32::max(0, damage.doubled().roll(rng))),
+        roll if roll + to_hit < armor => Attack::Miss,
+        _ => Attack::Hit(i32::max(0, damage.roll(rng))),
+    }
+}
+
+impl fmt::Display for Dice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}d{}", self.count, self.sides)?;
+        match self.bonus {
+            0 => Ok(()),
+            b if b > 0 => write!(f, "+{}", b),
+            b => write!(f, "{}", b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::random::{seeded, Stream};
+
+    /// Hands out the given rolls in order, whatever range was asked for.
+    struct Loaded(Vec<i32>);
+
+    impl Rng for Loaded {
+        fn next_int(&mut self) -> i32 {
+            assert!(!self.0.is_empty(), "Ran out of loaded rolls");
+            self.0.remove(0)
+        }
+
+        fn between(&mut self, _k: i32, _n: i32) -> i32 {
+            self.next_int()
+        }
+    }
+
+    fn loaded(rolls: &[i32]) -> Loaded {
+        Loaded(rolls.to_vec())
+    }
+
+    #[test]
+    fn parses_dice() {
+        assert_eq!(Dice::parse("1d8+1"), Ok(Dice::new(1, 8, 1)));
+        assert_eq!(Dice::parse("2d6"), Ok(Dice::new(2, 6, 0)));
+        assert_eq!(Dice::parse("d4-1"), Ok(Dice::new(1, 4, -1)));
+        assert_eq!(Dice::parse(" 3d10+2 "), Ok(Dice::new(3, 10, 2)));
+    }
+
+    #[test]
+    fn rejects_malformed_dice() {
+        for text in ["", "d", "2d", "6", "xd6", "1d6+", "1d6+x", "1d6d6", "0d6", "1d0", "-1d6"] {
+            assert!(Dice::parse(text).is_err(), "{:?} parsed", text);
+        }
+    }
+
+    #[test]
+    fn prints_the_way_it_parses() {
+        for text in ["1d8+1", "2d6", "1d4-1"] {
+            assert_eq!(Dice::parse(text).unwrap().to_string(), text);
+        }
+    }
+
+    #[test]
+    fn rolls_stay_in_range() {
+        let mut rng = seeded(7, Stream::Combat);
+        for (dice, low, high) in [(Dice::new(1, 8, 1), 2, 9), (Dice::new(2, 6, 0), 2, 12), (Dice::new(1, 1, 0), 1, 1)] {
+            let rolls: Vec<i32> = (0..1000).map(|_| dice.roll(&mut rng)).collect();
+            assert!(rolls.iter().all(|r| (low..=high).contains(r)), "{} went out of range", dice);
+            assert_eq!(*rolls.iter().min().unwrap(), low, "{} never rolled its lowest", dice);
+            assert_eq!(*rolls.iter().max().unwrap(), high, "{} never rolled its highest", dice);
+        }
+    }
+
+    #[test]
+    fn critical_doubles_only_the_dice() {
+        let dice = Dice::new(1, 8, 3);
+        assert_eq!(dice.doubled(), Dice::new(2, 8, 3));
+        // a 20 to hit, then two d8s for 5 and 6, with the +3 the once
+        assert_eq!(attack(&mut loaded(&[20, 5, 6]), 0, 30, dice), Attack::Critical(14));
+        assert_eq!(attack(&mut loaded(&[15, 5]), 0, 10, dice), Attack::Hit(8));
+    }
+
+    #[test]
+    fn natural_one_always_misses() {
+        assert_eq!(attack(&mut loaded(&[1]), 100, 0, Dice::new(1, 8, 1)), Attack::Fumble);
+        assert_eq!(attack(&mut loaded(&[9]), 0, 10, Dice::new(1, 8, 1)), Attack::Miss);
+        assert_eq!(attack(&mut loaded(&[10, 1]), 0, 10, Dice::new(1, 8, 1)), Attack::Hit(2));
+    }
+}
@@ -1,4 +1,10 @@
+pub use dice::Dice;
+pub use random::{MapRng, Seed, SpawnRng, StreamRng};
+pub use random_table::RandomTable;
 pub use rectangle::Rect;
+pub mod dice;
+pub mod random;
+pub mod random_table;
 pub mod rectangle;
 
 
@@ -7,7 +13,8 @@ pub trait Rng {
     fn between(&mut self, k: i32, n: i32 ) -> i32;
 }
 
-pub type RngResource = Box<dyn Rng + Send + Sync>;
+/// The combat stream, and what the map and spawn streams are made of.
+pub type RngResource = StreamRng;
 
 
 pub fn choose_element<'a,R: Rng + ?Sized, T> (rng: &mut R, slice: &'a [T]) -> Option<&'a T> {
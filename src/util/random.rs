@@ -0,0 +1,109 @@
+use super::{Rng, RngResource};
+use serde::{Deserialize, Serialize};
+use std::ops::{Deref, DerefMut};
+
+/// The seed the current game was started with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Seed(pub u64);
+
+/// Each part of the game that rolls dice gets its own stream off the seed, so
+/// rolling more in one of them doesnt reshuffle what the others produce.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stream {
+    Map,
+    Spawn,
+    Combat,
+}
+
+/// Stream used for generating the levels.
+pub struct MapRng(pub RngResource);
+
+/// Stream used for placing and picking monsters and items.
+pub struct SpawnRng(pub RngResource);
+
+impl Deref for MapRng {
+    type Target = RngResource;
+    fn deref(&self) -> &RngResource {
+        &self.0
+    }
+}
+
+impl DerefMut for MapRng {
+    fn deref_mut(&mut self) -> &mut RngResource {
+        &mut self.0
+    }
+}
+
+impl Deref for SpawnRng {
+    type Target = RngResource;
+    fn deref(&self) -> &RngResource {
+        &self.0
+    }
+}
+
+impl DerefMut for SpawnRng {
+    fn deref_mut(&mut self) -> &mut RngResource {
+        &mut self.0
+    }
+}
+
+/// Splitmix64, small enough that its whole state goes in the save and the
+/// streams carry on where they left off after a load.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct StreamRng {
+    state: u64,
+}
+
+impl StreamRng {
+    pub fn new(seed: u64) -> StreamRng {
+        StreamRng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let next = splitmix(self.state);
+        self.state = self.state.wrapping_add(GOLDEN_GAMMA);
+        next
+    }
+}
+
+impl Rng for StreamRng {
+    fn next_int(&mut self) -> i32 {
+        (self.next_u64() >> 32) as i32
+    }
+
+    fn between(&mut self, k: i32, n: i32) -> i32 {
+        assert!(k < n, "Nothing to roll between {} and {}", k, n);
+        // scale the top 32 bits into the range, no modulo so no favourites
+        let range = (i64::from(n) - i64::from(k)) as u64;
+        k + (((self.next_u64() >> 32) * range) >> 32) as i32
+    }
+}
+
+/// A seed off the clock, for when nobody asked for a particular one.
+pub fn random_seed() -> u64 {
+    rltk::RandomNumberGenerator::new().next_u64()
+}
+
+pub fn seeded(seed: u64, stream: Stream) -> RngResource {
+    let salt = match stream {
+        Stream::Map => 1,
+        Stream::Spawn => 2,
+        Stream::Combat => 3,
+    };
+    StreamRng::new(splitmix(seed ^ splitmix(salt)))
+}
+
+/// Seed for everything generated on one level of the dungeon.
+pub fn level_seed(seed: u64, depth: i32) -> u64 {
+    splitmix(seed ^ splitmix(0x100 + depth as u64))
+}
+
+const GOLDEN_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+// one step of splitmix64, also spreads nearby seeds out so they dont start off in similar states
+fn splitmix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(GOLDEN_GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
@@ -0,0 +1,85 @@
+use super::Rng;
+
+/// Picks names at random, with some names coming up more often than others.
+#[derive(Clone, Debug, Default)]
+pub struct RandomTable {
+    entries: Vec<(String, i32)>,
+    total_weight: i32,
+}
+
+impl RandomTable {
+    pub fn new() -> RandomTable {
+        RandomTable::default()
+    }
+
+    pub fn add<S: ToString>(mut self, name: S, weight: i32) -> RandomTable {
+        if weight > 0 {
+            self.total_weight += weight;
+            self.entries.push((name.to_string(), weight));
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn roll<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<&str> {
+        if self.total_weight == 0 {
+            return None;
+        }
+        let mut roll = rng.between(0, self.total_weight);
+        for (name, weight) in self.entries.iter() {
+            if roll < *weight {
+                return Some(name);
+            }
+            roll -= weight;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::random::{seeded, Stream};
+
+    #[test]
+    fn empty_table_rolls_nothing() {
+        let mut rng = seeded(1, Stream::Spawn);
+        let table = RandomTable::new();
+        assert!(table.is_empty());
+        assert_eq!(table.roll(&mut rng), None);
+
+        // nothing with a weight of zero or less goes in
+        let table = RandomTable::new().add("Orc", 0).add("Goblin", -2);
+        assert!(table.is_empty());
+        assert_eq!(table.roll(&mut rng), None);
+    }
+
+    #[test]
+    fn rolls_follow_the_weights() {
+        let mut rng = seeded(1, Stream::Spawn);
+        let table = RandomTable::new().add("Orc", 1).add("Goblin", 3).add("Troll", 0);
+        let rolls = 8000;
+        let mut orcs = 0;
+        let mut goblins = 0;
+        for _ in 0..rolls {
+            match table.roll(&mut rng) {
+                Some("Orc") => orcs += 1,
+                Some("Goblin") => goblins += 1,
+                other => panic!("Rolled {:?}", other),
+            }
+        }
+        // a quarter and three quarters, give or take
+        assert!((1800..2200).contains(&orcs), "{} orcs", orcs);
+        assert!((5800..6200).contains(&goblins), "{} goblins", goblins);
+    }
+
+    #[test]
+    fn single_entry_always_comes_up() {
+        let mut rng = seeded(1, Stream::Spawn);
+        let table = RandomTable::new().add("Rat", 5);
+        assert!((0..100).all(|_| table.roll(&mut rng) == Some("Rat")));
+    }
+}
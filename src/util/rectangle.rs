@@ -1,4 +1,6 @@
-#[derive(PartialEq, Clone, Copy, Debug)]
+use serde::{Deserialize, Serialize};
+
+#[derive(PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Rect {
     pub x1: i32,
     pub x2: i32,
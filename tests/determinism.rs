@@ -0,0 +1,65 @@
+use tetra::headless::Headless;
+use tetra::player::Command;
+use tetra::raws::RawMaster;
+use tetra::replay::{self, Digest, Recorder};
+use tetra::State;
+
+/// The same wander every time, bumping into whatever is in the way.
+fn commands() -> Vec<Command> {
+    let steps = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+    (0..200)
+        .map(|i| match i % 7 {
+            6 => Command::Wait,
+            _ => {
+                let (dx, dy) = steps[(i / 5) % steps.len()];
+                Command::Move { dx, dy }
+            }
+        })
+        .collect()
+}
+
+/// Digest of the world after every command that got played.
+fn play(seed: u64) -> Vec<Digest> {
+    let mut game = Headless::new(seed);
+    let mut digests = vec![Digest::of(&game.state.ecs)];
+    for command in commands() {
+        if game.player_dead() {
+            break;
+        }
+        game.step(command);
+        digests.push(Digest::of(&game.state.ecs));
+    }
+    digests
+}
+
+#[test]
+fn same_seed_same_game() {
+    for seed in [1, 42] {
+        assert_eq!(play(seed), play(seed), "seed {} played out differently", seed);
+    }
+}
+
+#[test]
+fn different_seed_different_game() {
+    assert_ne!(play(1).last(), play(2).last());
+}
+
+#[test]
+fn turned_down_commands_replay() {
+    let path = std::env::temp_dir().join(format!("tetra-determinism-{}.replay", std::process::id()));
+    let mut state = State::new(5);
+    state.recorder = Some(Recorder::create(&path, 5).unwrap());
+    let mut game = Headless::from_state(state);
+    // the level never starts on the stairs down, and there are no open doors yet
+    let mut session = vec![Command::Descend, Command::CloseDoor];
+    session.extend(commands().into_iter().take(20));
+    session.push(Command::Descend);
+    game.run(session);
+    drop(game);
+
+    let replayed = replay::replay(&path, RawMaster::embedded());
+    std::fs::remove_file(&path).unwrap();
+    if let Err(e) = replayed {
+        panic!("{}", e);
+    }
+}